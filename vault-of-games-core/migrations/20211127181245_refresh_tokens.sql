@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS refresh_tokens
+(
+    id         TEXT
+        CONSTRAINT refresh_tokens_pk
+            PRIMARY KEY,
+    user_id    TEXT      NOT NULL,
+    family_id  TEXT      NOT NULL,
+    token_hash TEXT      NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    rotated_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS refresh_tokens_id_index
+    ON refresh_tokens (id);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id_index
+    ON refresh_tokens (family_id);
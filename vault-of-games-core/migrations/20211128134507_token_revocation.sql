@@ -0,0 +1,16 @@
+ALTER TABLE users
+    ADD COLUMN token_generation INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS revoked_tokens
+(
+    jwt_id     TEXT
+        CONSTRAINT revoked_tokens_pk
+            PRIMARY KEY,
+    user_id    TEXT      NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
@@ -0,0 +1,5 @@
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+
+ALTER TABLE users
+    ADD COLUMN disabled_at TIMESTAMP;
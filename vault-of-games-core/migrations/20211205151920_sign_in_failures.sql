@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS sign_in_failures
+(
+    key             TEXT
+        CONSTRAINT sign_in_failures_pk
+            PRIMARY KEY,
+    failures        INTEGER   NOT NULL,
+    locked_until    TIMESTAMP,
+    last_failure_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS personal_access_tokens
+(
+    id           TEXT
+        CONSTRAINT personal_access_tokens_pk
+            PRIMARY KEY,
+    user_id      TEXT NOT NULL,
+    name         TEXT NOT NULL,
+    token_hash   TEXT NOT NULL UNIQUE,
+    scopes       TEXT NOT NULL,
+    expires_at   TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at   TIMESTAMP,
+    created_at   TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at   TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS personal_access_tokens_id_index
+    ON personal_access_tokens (id);
@@ -0,0 +1,26 @@
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT;
+
+ALTER TABLE users
+    ADD COLUMN totp_enabled_at TIMESTAMP;
+
+ALTER TABLE users
+    ADD COLUMN totp_last_used_step INTEGER;
+
+CREATE TABLE IF NOT EXISTS recovery_codes
+(
+    id         TEXT
+        CONSTRAINT recovery_codes_pk
+            PRIMARY KEY,
+    user_id    TEXT NOT NULL,
+    code_hash  TEXT NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_index
+    ON recovery_codes (user_id);
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS password_reset_tokens
+(
+    id         TEXT
+        CONSTRAINT password_reset_tokens_pk
+            PRIMARY KEY,
+    user_id    TEXT      NOT NULL,
+    token_hash TEXT      NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS password_reset_tokens_user_id_index
+    ON password_reset_tokens (user_id);
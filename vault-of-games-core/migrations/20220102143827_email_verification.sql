@@ -0,0 +1,28 @@
+ALTER TABLE users
+    ADD COLUMN email TEXT;
+
+ALTER TABLE users
+    ADD COLUMN email_verified_at TIMESTAMP;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_index
+    ON users (email);
+
+CREATE TABLE IF NOT EXISTS email_verification_tokens
+(
+    id         TEXT
+        CONSTRAINT email_verification_tokens_pk
+            PRIMARY KEY,
+    user_id    TEXT      NOT NULL,
+    email      TEXT      NOT NULL,
+    token_hash TEXT      NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS email_verification_tokens_user_id_index
+    ON email_verification_tokens (user_id);
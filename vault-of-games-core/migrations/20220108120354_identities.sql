@@ -0,0 +1,32 @@
+CREATE TABLE IF NOT EXISTS identities
+(
+    id           TEXT
+        CONSTRAINT identities_pk
+            PRIMARY KEY,
+    user_id      TEXT NOT NULL,
+    provider     TEXT NOT NULL,
+    subject      TEXT NOT NULL,
+    email        TEXT,
+    last_used_at TIMESTAMP,
+    created_at   TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (provider, subject),
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS identities_user_id_index
+    ON identities (user_id);
+
+CREATE TABLE IF NOT EXISTS oidc_authorizations
+(
+    state         TEXT
+        CONSTRAINT oidc_authorizations_pk
+            PRIMARY KEY,
+    provider      TEXT      NOT NULL,
+    code_verifier TEXT      NOT NULL,
+    nonce         TEXT      NOT NULL,
+    expires_at    TIMESTAMP NOT NULL,
+    created_at    TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS sessions
+(
+    id           TEXT
+        CONSTRAINT sessions_pk
+            PRIMARY KEY,
+    user_id      TEXT NOT NULL,
+    user_agent   TEXT,
+    ip_address   TEXT,
+    created_at   TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP,
+    revoked_at   TIMESTAMP,
+    FOREIGN KEY (user_id)
+        REFERENCES users (id)
+        ON DELETE CASCADE
+        ON UPDATE NO ACTION
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_index
+    ON sessions (user_id);
+
+-- Refresh token families issued so far become sessions without client metadata.
+INSERT INTO sessions (id, user_id, created_at, last_seen_at, revoked_at)
+SELECT family_id, user_id, MIN(created_at), MAX(created_at), MAX(revoked_at)
+FROM refresh_tokens
+GROUP BY family_id, user_id;
@@ -0,0 +1,36 @@
+-- Actors and targets are not foreign keys, so that the events outlive the accounts they are about.
+CREATE TABLE IF NOT EXISTS audit_events
+(
+    id         TEXT
+        CONSTRAINT audit_events_pk
+            PRIMARY KEY,
+    actor_id   TEXT,
+    action     TEXT      NOT NULL,
+    target_id  TEXT,
+    ip_address TEXT,
+    user_agent TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_index
+    ON audit_events (actor_id, created_at);
+
+CREATE INDEX IF NOT EXISTS audit_events_target_id_index
+    ON audit_events (target_id, created_at);
+
+CREATE INDEX IF NOT EXISTS audit_events_created_at_index
+    ON audit_events (created_at);
+
+CREATE TRIGGER IF NOT EXISTS audit_events_no_update
+    BEFORE UPDATE
+    ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit events are append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_events_no_delete
+    BEFORE DELETE
+    ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit events are append-only');
+END;
@@ -0,0 +1,5 @@
+ALTER TABLE users
+    ADD COLUMN deletion_scheduled_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS users_deletion_scheduled_at_index
+    ON users (deletion_scheduled_at);
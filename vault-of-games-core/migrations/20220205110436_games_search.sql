@@ -0,0 +1,88 @@
+-- Searchable copy of each game, kept in sync with its title, note and category names by the
+-- triggers below. Categories are stored space-separated.
+CREATE VIRTUAL TABLE IF NOT EXISTS games_search USING fts5
+(
+    game_id UNINDEXED,
+    user_id UNINDEXED,
+    title,
+    note,
+    categories,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO games_search (game_id, user_id, title, note, categories)
+SELECT g.id,
+       g.user_id,
+       g.title,
+       g.note,
+       (SELECT group_concat(c.name, ' ')
+        FROM games_categories gc
+                 JOIN categories c on c.id = gc.category_id
+        WHERE gc.game_id = g.id)
+FROM games g;
+
+CREATE TRIGGER IF NOT EXISTS games_search_insert
+    AFTER INSERT
+    ON games
+BEGIN
+    INSERT INTO games_search (game_id, user_id, title, note, categories)
+    VALUES (NEW.id, NEW.user_id, NEW.title, NEW.note, NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS games_search_update
+    AFTER UPDATE OF title, note
+    ON games
+BEGIN
+    UPDATE games_search
+    SET title = NEW.title,
+        note  = NEW.note
+    WHERE game_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS games_search_delete
+    AFTER DELETE
+    ON games
+BEGIN
+    DELETE
+    FROM games_search
+    WHERE game_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS games_search_categories_insert
+    AFTER INSERT
+    ON games_categories
+BEGIN
+    UPDATE games_search
+    SET categories = (SELECT group_concat(c.name, ' ')
+                      FROM games_categories gc
+                               JOIN categories c on c.id = gc.category_id
+                      WHERE gc.game_id = NEW.game_id)
+    WHERE game_id = NEW.game_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS games_search_categories_delete
+    AFTER DELETE
+    ON games_categories
+BEGIN
+    UPDATE games_search
+    SET categories = (SELECT group_concat(c.name, ' ')
+                      FROM games_categories gc
+                               JOIN categories c on c.id = gc.category_id
+                      WHERE gc.game_id = OLD.game_id)
+    WHERE game_id = OLD.game_id;
+END;
+
+-- Renaming a category changes every game that is in it.
+CREATE TRIGGER IF NOT EXISTS games_search_category_rename
+    AFTER UPDATE OF name
+    ON categories
+BEGIN
+    UPDATE games_search
+    SET categories = (SELECT group_concat(c.name, ' ')
+                      FROM games_categories gc
+                               JOIN categories c on c.id = gc.category_id
+                      WHERE gc.game_id = games_search.game_id)
+    WHERE game_id IN (SELECT game_id
+                      FROM games_categories
+                      WHERE category_id = NEW.id);
+END;
@@ -0,0 +1,4 @@
+-- Incremented on every change, so that clients can tell whether the game they are about to
+-- change is still the one they have seen.
+ALTER TABLE games
+    ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
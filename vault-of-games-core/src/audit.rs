@@ -0,0 +1,125 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Type};
+use uuid::Uuid;
+
+use crate::{
+    authentication::sessions::ClientMetadata, database::DatabaseConnectionPool,
+    error::ProcessorError,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AuditAction {
+    SignUp,
+    SignIn,
+    /// Wrong password or second factor.
+    SignInFailed,
+    SignOut,
+    SignOutEverywhere,
+    SessionRevoked,
+    PasswordChanged,
+    PasswordReset,
+    AccountUpdated,
+    AccountDeletionScheduled,
+    /// Signing in during the grace period keeps the account.
+    AccountDeletionCancelled,
+    AccountDeleted,
+    DataExported,
+    TokenCreated,
+    TokenRevoked,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    RecoveryCodesRegenerated,
+    /// A validly signed access token was presented after it had been revoked.
+    RevokedTokenUsed,
+    UserRoleChanged,
+    UserDisabled,
+    UserEnabled,
+    /// A sign-in lockout was lifted before it ended on its own.
+    UserUnlocked,
+    CategoryUpdated,
+    CategoryDeleted,
+}
+
+/// Entry of the append-only security audit log.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEvent {
+    pub id: String,
+    /// User that performed the action, if known.
+    pub actor_id: Option<String>,
+    pub action: AuditAction,
+    /// User, session, token or category that the action applied to.
+    pub target_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+}
+
+/// Event that is about to be written to the audit log.
+pub struct AuditRecord<'a> {
+    action: AuditAction,
+    actor_id: Option<&'a str>,
+    target_id: Option<&'a str>,
+    client: Option<&'a ClientMetadata>,
+}
+
+impl<'a> AuditRecord<'a> {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_id: None,
+            target_id: None,
+            client: None,
+        }
+    }
+
+    pub fn with_actor(mut self, actor_id: &'a str) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn with_target(mut self, target_id: &'a str) -> Self {
+        self.target_id = Some(target_id);
+        self
+    }
+
+    pub fn with_client(mut self, client: &'a ClientMetadata) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Writes the event. The action has already taken place by then, so a failed write is logged
+    /// instead of failing the request that performed it.
+    pub async fn record(self, pool: &DatabaseConnectionPool) {
+        if let Err(error) = self.insert(pool).await {
+            tracing::error!("Failed to record {:?} audit event: {}", self.action, error);
+        }
+    }
+
+    async fn insert(&self, pool: &DatabaseConnectionPool) -> Result<(), ProcessorError> {
+        let id = Uuid::new_v4().to_string();
+        let ip_address = self.client.map(|client| client.address.to_string());
+        let user_agent = self.client.and_then(|client| client.user_agent.as_deref());
+        let now = Utc::now();
+
+        query!(
+            "
+            INSERT INTO audit_events (id, actor_id, action, target_id, ip_address, user_agent, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);
+            ",
+            id,
+            self.actor_id,
+            self.action,
+            self.target_id,
+            ip_address,
+            user_agent,
+            now,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use sqlx::query;
+
+use crate::{
+    audit::{AuditAction, AuditRecord},
+    configuration::AccountDeletionConfiguration,
+    database::DatabaseConnectionPool,
+    error::ProcessorError,
+};
+
+use super::revocation;
+
+/// How often accounts whose grace period has run out are looked for.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Schedules the account for removal once the grace period is over and signs it out everywhere
+/// in the meantime. Asking again keeps the original schedule.
+pub async fn schedule_deletion(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    configuration: &AccountDeletionConfiguration,
+) -> Result<DateTime<Utc>, ProcessorError> {
+    let scheduled_for = Utc::now() + ChronoDuration::days(configuration.grace_period_days);
+
+    let user = query!(
+        "
+        UPDATE users
+        SET deletion_scheduled_at = COALESCE(deletion_scheduled_at, ?1)
+        WHERE id = ?2;
+        ",
+        scheduled_for,
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    if user.rows_affected() == 0 {
+        return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+    }
+
+    revocation::revoke_all_tokens(pool, user_id).await?;
+
+    let user = query!(
+        r#"
+        SELECT deletion_scheduled_at as "deletion_scheduled_at!: DateTime<Utc>"
+        FROM users
+        WHERE id = ?;
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(user.deletion_scheduled_at)
+}
+
+/// Returns whether a scheduled deletion was cancelled.
+pub async fn cancel_deletion(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+) -> Result<bool, ProcessorError> {
+    let user = query!(
+        "
+        UPDATE users
+        SET deletion_scheduled_at = NULL
+        WHERE id = ? AND deletion_scheduled_at IS NOT NULL;
+        ",
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(user.rows_affected() == 1)
+}
+
+/// Removes the account along with everything that belongs to it.
+pub async fn delete_account(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+) -> Result<(), ProcessorError> {
+    let user = query!(
+        "
+        DELETE
+        FROM users
+        WHERE id = ?;
+        ",
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    if user.rows_affected() == 0 {
+        return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+    }
+
+    Ok(())
+}
+
+/// Removes the accounts whose grace period has run out.
+pub async fn purge_due_accounts(pool: &DatabaseConnectionPool) -> Result<(), ProcessorError> {
+    let now = Utc::now();
+
+    let users = query!(
+        r#"
+        SELECT id as "id!"
+        FROM users
+        WHERE deletion_scheduled_at <= ?;
+        "#,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for user in users {
+        // The user may have signed in since the accounts were looked up.
+        let deletion = query!(
+            "
+            DELETE
+            FROM users
+            WHERE id = ?1 AND deletion_scheduled_at <= ?2;
+            ",
+            user.id,
+            now,
+        )
+        .execute(pool)
+        .await?;
+
+        if deletion.rows_affected() == 1 {
+            AuditRecord::new(AuditAction::AccountDeleted)
+                .with_target(&user.id)
+                .record(pool)
+                .await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes accounts periodically, for as long as the server runs.
+pub async fn purge_due_accounts_periodically(pool: DatabaseConnectionPool) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = purge_due_accounts(&pool).await {
+            tracing::error!(
+                "Failed to remove accounts scheduled for deletion: {}",
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn insert_user(
+        pool: &DatabaseConnectionPool,
+        id: &str,
+        deletion_scheduled_at: Option<DateTime<Utc>>,
+    ) {
+        sqlx::query(
+            "INSERT INTO users (id, username, password, deletion_scheduled_at) VALUES (?1, ?1, '', ?2);",
+        )
+        .bind(id)
+        .bind(deletion_scheduled_at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn user_exists(pool: &DatabaseConnectionPool, id: &str) -> bool {
+        sqlx::query("SELECT id FROM users WHERE id = ?;")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn purging_removes_only_accounts_that_are_due() {
+        let pool = testing::database().await;
+        let now = Utc::now();
+        insert_user(&pool, "due", Some(now - ChronoDuration::minutes(1))).await;
+        insert_user(&pool, "pending", Some(now + ChronoDuration::days(1))).await;
+        insert_user(&pool, "kept", None).await;
+
+        sqlx::query(
+            "INSERT INTO games (id, user_id, title) VALUES ('game', 'due', 'Outer Wilds');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        purge_due_accounts(&pool).await.unwrap();
+
+        assert!(!user_exists(&pool, "due").await);
+        assert!(user_exists(&pool, "pending").await);
+        assert!(user_exists(&pool, "kept").await);
+
+        let games: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM games;")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(games, 0);
+
+        let deletions: Vec<String> = sqlx::query_scalar(
+            "SELECT target_id FROM audit_events WHERE action = 'account_deleted';",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(deletions, vec!["due".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn scheduling_again_keeps_the_original_date() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice", None).await;
+
+        let first = schedule_deletion(
+            &pool,
+            "alice",
+            &AccountDeletionConfiguration {
+                grace_period_days: 30,
+            },
+        )
+        .await
+        .unwrap();
+        let second = schedule_deletion(
+            &pool,
+            "alice",
+            &AccountDeletionConfiguration {
+                grace_period_days: 60,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn cancelling_keeps_the_account() {
+        let pool = testing::database().await;
+        insert_user(
+            &pool,
+            "alice",
+            Some(Utc::now() - ChronoDuration::minutes(1)),
+        )
+        .await;
+
+        assert!(cancel_deletion(&pool, "alice").await.unwrap());
+        assert!(!cancel_deletion(&pool, "alice").await.unwrap());
+
+        purge_due_accounts(&pool).await.unwrap();
+
+        assert!(user_exists(&pool, "alice").await);
+    }
+}
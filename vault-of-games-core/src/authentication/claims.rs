@@ -0,0 +1,37 @@
+use jwt_simple::prelude::JWTClaims;
+use serde::{Deserialize, Serialize};
+
+use super::{role::Role, scope::Scopes};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccessTokenClaims {
+    /// Refresh token family the access token was issued for.
+    #[serde(rename = "sid")]
+    pub session_id: String,
+    /// Value of `users.token_generation` at the time of signing.
+    #[serde(rename = "gen")]
+    pub token_generation: i64,
+    pub role: Role,
+    /// Only personal access tokens are restricted to scopes, signed access tokens grant
+    /// everything their role allows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Scopes>,
+}
+
+impl AccessTokenClaims {
+    pub fn new(session_id: String, token_generation: i64, role: Role) -> Self {
+        Self {
+            session_id,
+            token_generation,
+            role,
+            scopes: None,
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: Scopes) -> Self {
+        self.scopes = Some(scopes);
+        self
+    }
+}
+
+pub type AuthorizationClaims = JWTClaims<AccessTokenClaims>;
@@ -0,0 +1,223 @@
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    Json,
+};
+use headers::{Cookie, HeaderMapExt};
+use hyper::{
+    header::{HeaderMap, HeaderValue, SET_COOKIE},
+    Method,
+};
+
+use crate::{
+    configuration::{CookieConfiguration, TokenTransport},
+    error::ProcessorError,
+};
+
+use super::{
+    error::AuthenticationError,
+    tokens::{self, REFRESH_TOKEN_LIFETIME_DAYS},
+    AuthenticationResponse, CookieSessionResponse, SignInResponse,
+};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "vog_access_token";
+pub const REFRESH_TOKEN_COOKIE: &str = "vog_refresh_token";
+/// Readable by scripts of the web client, which repeat it in [`CSRF_TOKEN_HEADER`].
+pub const CSRF_TOKEN_COOKIE: &str = "vog_csrf_token";
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+
+/// The refresh token is only sent along to the endpoint that rotates it.
+pub const REFRESH_TOKEN_COOKIE_PATH: &str = "/v1/users/token/refresh";
+
+/// Session cookies and the CSRF token header sent with a request.
+#[derive(Clone, Debug, Default)]
+pub struct CookieCredentials {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    csrf_cookie: Option<String>,
+    csrf_header: Option<String>,
+}
+
+impl CookieCredentials {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let cookies = headers.typed_get::<Cookie>();
+        let cookie = |name: &str| {
+            cookies
+                .as_ref()
+                .and_then(|cookies| cookies.get(name))
+                .map(str::to_string)
+        };
+
+        Self {
+            access_token: cookie(ACCESS_TOKEN_COOKIE),
+            refresh_token: cookie(REFRESH_TOKEN_COOKIE),
+            csrf_cookie: cookie(CSRF_TOKEN_COOKIE),
+            csrf_header: headers
+                .get(CSRF_TOKEN_HEADER)
+                .and_then(|header_value| header_value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+
+    /// Whether the CSRF header repeats the CSRF cookie, which other sites cannot read to do so.
+    pub fn csrf_token_matches(&self) -> bool {
+        match (&self.csrf_cookie, &self.csrf_header) {
+            (Some(cookie), Some(header)) => {
+                !cookie.is_empty() && constant_time_eq(cookie.as_bytes(), header.as_bytes())
+            }
+            _ => false,
+        }
+    }
+
+    /// Only requests that may change state have to prove that they were not forged.
+    pub fn passes_csrf_check(&self, method: &Method) -> bool {
+        matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) || self.csrf_token_matches()
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for CookieCredentials
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(request
+            .headers()
+            .map(Self::from_headers)
+            .unwrap_or_default())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn cookie(
+    name: &str,
+    value: &str,
+    path: &str,
+    max_age: i64,
+    http_only: bool,
+    configuration: &CookieConfiguration,
+) -> Result<HeaderValue, ProcessorError> {
+    let mut cookie = format!(
+        "{}={}; Path={}; Max-Age={}; SameSite=Strict",
+        name, value, path, max_age
+    );
+
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+
+    if configuration.secure {
+        cookie.push_str("; Secure");
+    }
+
+    if let Some(domain) = &configuration.domain {
+        cookie.push_str(&format!("; Domain={}", domain));
+    }
+
+    HeaderValue::from_str(&cookie).map_err(|_| AuthenticationError::TokenCreation.into())
+}
+
+fn session_cookies(
+    access_token: &str,
+    refresh_token: &str,
+    csrf_token: &str,
+    access_token_max_age: i64,
+    refresh_token_max_age: i64,
+    configuration: &CookieConfiguration,
+) -> Result<HeaderMap, ProcessorError> {
+    let mut headers = HeaderMap::new();
+
+    headers.append(
+        SET_COOKIE,
+        cookie(
+            ACCESS_TOKEN_COOKIE,
+            access_token,
+            "/",
+            access_token_max_age,
+            true,
+            configuration,
+        )?,
+    );
+    headers.append(
+        SET_COOKIE,
+        cookie(
+            REFRESH_TOKEN_COOKIE,
+            refresh_token,
+            REFRESH_TOKEN_COOKIE_PATH,
+            refresh_token_max_age,
+            true,
+            configuration,
+        )?,
+    );
+    headers.append(
+        SET_COOKIE,
+        cookie(
+            CSRF_TOKEN_COOKIE,
+            csrf_token,
+            "/",
+            refresh_token_max_age,
+            false,
+            configuration,
+        )?,
+    );
+
+    Ok(headers)
+}
+
+/// Hands freshly issued tokens to the client in the way that the configuration asks for.
+pub fn deliver_tokens(
+    response: AuthenticationResponse,
+    configuration: &CookieConfiguration,
+) -> Result<(HeaderMap, Json<SignInResponse>), ProcessorError> {
+    if !configuration.transport.accepts_cookie() {
+        return Ok((
+            HeaderMap::new(),
+            Json(SignInResponse::Authenticated(response)),
+        ));
+    }
+
+    let csrf_token = tokens::generate_opaque_token()?;
+    let headers = session_cookies(
+        &response.access_token,
+        &response.refresh_token,
+        &csrf_token,
+        response.expires_in as i64,
+        REFRESH_TOKEN_LIFETIME_DAYS * 24 * 60 * 60,
+        configuration,
+    )?;
+
+    let body = match configuration.transport {
+        TokenTransport::Cookie => SignInResponse::CookieAuthenticated(CookieSessionResponse::new(
+            csrf_token,
+            response.expires_in,
+        )),
+        _ => SignInResponse::Authenticated(response),
+    };
+
+    Ok((headers, Json(body)))
+}
+
+/// Headers that remove the session cookies on sign-out, if cookies are in use at all.
+pub fn clear_session_cookies(
+    configuration: &CookieConfiguration,
+) -> Result<HeaderMap, ProcessorError> {
+    if !configuration.transport.accepts_cookie() {
+        return Ok(HeaderMap::new());
+    }
+
+    session_cookies("", "", "", 0, 0, configuration)
+}
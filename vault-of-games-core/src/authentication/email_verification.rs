@@ -0,0 +1,334 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Configuration, EmailVerificationConfiguration},
+    database::DatabaseConnectionPool,
+    error::ProcessorError,
+    mail::{Mail, Mailer},
+    validation::ValidationErrors,
+};
+
+use super::{error::AuthenticationError, tokens};
+
+/// Loose sanity check of an email address, the verification mail is the real one.
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Trims and lowercases the address, recording a validation error if it does not look like one.
+pub fn normalize_email(email: &str, errors: &mut ValidationErrors) -> Option<String> {
+    let email = email.trim().to_lowercase();
+
+    if !is_valid_email(&email) {
+        errors.add("email", "must be a valid email address");
+
+        return None;
+    }
+
+    Some(email)
+}
+
+/// Rejects email addresses that belong to any account but the one identified by `user_id`.
+pub async fn ensure_email_available(
+    pool: &DatabaseConnectionPool,
+    email: &str,
+    user_id: Option<&str>,
+) -> Result<(), ProcessorError> {
+    let record = query!(
+        r#"
+        SELECT id as "id!"
+        FROM users
+        WHERE email = ?;
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    match record {
+        Some(record) if Some(record.id.as_str()) != user_id => {
+            Err(AuthenticationError::EmailTaken.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Issues a single-use token that verifies `email` for the user, invalidating the ones issued
+/// before it.
+pub async fn issue_verification_token(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    email: &str,
+    configuration: &EmailVerificationConfiguration,
+) -> Result<String, ProcessorError> {
+    let token = tokens::generate_opaque_token()?;
+    let token_hash = tokens::hash_opaque_token(&token)?;
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(configuration.token_lifetime_minutes);
+
+    query!(
+        "
+        DELETE
+        FROM email_verification_tokens
+        WHERE user_id = ?1 OR expires_at < ?2;
+        ",
+        user_id,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    query!(
+        "
+        INSERT INTO email_verification_tokens (id, user_id, email, token_hash, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        ",
+        id,
+        user_id,
+        email,
+        token_hash,
+        expires_at,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Mails a verification link for `email` to that very address.
+pub async fn send_verification_mail(
+    pool: &DatabaseConnectionPool,
+    mailer: &dyn Mailer,
+    configuration: &Configuration,
+    user_id: &str,
+    email: &str,
+) -> Result<(), ProcessorError> {
+    let token =
+        issue_verification_token(pool, user_id, email, &configuration.email_verification).await?;
+
+    let mail = Mail::new(
+        email.to_string(),
+        "Verify your Vault of Games email address".to_string(),
+        format!(
+            "Follow this link within {} minutes to verify your email address:\n\n{}/email/verify?token={}\n\nIf you did not sign up for Vault of Games, you can ignore this message.",
+            configuration.email_verification.token_lifetime_minutes,
+            configuration.mail.public_url.trim_end_matches('/'),
+            token
+        ),
+    );
+
+    if let Err(error) = mailer.send(mail).await {
+        tracing::error!("Failed to send email verification mail: {}", error);
+    }
+
+    Ok(())
+}
+
+/// Marks the address that the token was issued for as verified, unless the user has changed it
+/// since.
+pub async fn verify_email(
+    pool: &DatabaseConnectionPool,
+    token: &str,
+) -> Result<(), ProcessorError> {
+    let token_hash = tokens::hash_opaque_token(token)?;
+    let now = Utc::now();
+
+    let record = query!(
+        r#"
+        SELECT id as "id!",
+            user_id as "user_id!",
+            email as "email!",
+            expires_at as "expires_at!: DateTime<Utc>",
+            used_at as "used_at?: DateTime<Utc>"
+        FROM email_verification_tokens
+        WHERE token_hash = ?;
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AuthenticationError::InvalidVerificationToken)?;
+
+    if record.used_at.is_some() || record.expires_at <= now {
+        return Err(AuthenticationError::InvalidVerificationToken.into());
+    }
+
+    let redemption = query!(
+        "
+        UPDATE email_verification_tokens
+        SET used_at = ?1
+        WHERE id = ?2 AND used_at IS NULL;
+        ",
+        now,
+        record.id,
+    )
+    .execute(pool)
+    .await?;
+
+    if redemption.rows_affected() == 0 {
+        return Err(AuthenticationError::InvalidVerificationToken.into());
+    }
+
+    let verification = query!(
+        "
+        UPDATE users
+        SET email_verified_at = ?1,
+            updated_at        = ?1
+        WHERE id = ?2 AND email = ?3;
+        ",
+        now,
+        record.user_id,
+        record.email,
+    )
+    .execute(pool)
+    .await?;
+
+    if verification.rows_affected() == 0 {
+        return Err(AuthenticationError::InvalidVerificationToken.into());
+    }
+
+    Ok(())
+}
+
+/// Rejects unverified accounts where the configuration demands a verified email address.
+pub fn ensure_verified(
+    email_verified_at: Option<DateTime<Utc>>,
+    required: bool,
+) -> Result<(), AuthenticationError> {
+    if required && email_verified_at.is_none() {
+        return Err(AuthenticationError::EmailNotVerified);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn user_with_email(pool: &DatabaseConnectionPool, email: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+
+        sqlx::query("INSERT INTO users (id, username, password, email) VALUES (?, ?, '', ?);")
+            .bind(&id)
+            .bind(&id)
+            .bind(email)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        id
+    }
+
+    async fn email_verified_at(
+        pool: &DatabaseConnectionPool,
+        user_id: &str,
+    ) -> Option<DateTime<Utc>> {
+        sqlx::query_scalar("SELECT email_verified_at FROM users WHERE id = ?;")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn is_invalid_token(result: Result<(), ProcessorError>) -> bool {
+        matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidVerificationToken
+            ))
+        )
+    }
+
+    #[tokio::test]
+    async fn tokens_verify_the_address_only_once() {
+        let pool = testing::database().await;
+        let configuration = testing::configuration().email_verification;
+        let user_id = user_with_email(&pool, "alice@example.com").await;
+        let token = issue_verification_token(&pool, &user_id, "alice@example.com", &configuration)
+            .await
+            .unwrap();
+
+        assert!(email_verified_at(&pool, &user_id).await.is_none());
+
+        verify_email(&pool, &token).await.unwrap();
+
+        assert!(email_verified_at(&pool, &user_id).await.is_some());
+        assert!(is_invalid_token(verify_email(&pool, &token).await));
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_rejected() {
+        let pool = testing::database().await;
+        let configuration = EmailVerificationConfiguration {
+            token_lifetime_minutes: -1,
+            ..testing::configuration().email_verification
+        };
+        let user_id = user_with_email(&pool, "alice@example.com").await;
+        let token = issue_verification_token(&pool, &user_id, "alice@example.com", &configuration)
+            .await
+            .unwrap();
+
+        assert!(is_invalid_token(verify_email(&pool, &token).await));
+        assert!(email_verified_at(&pool, &user_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn new_tokens_replace_the_old_ones() {
+        let pool = testing::database().await;
+        let configuration = testing::configuration().email_verification;
+        let user_id = user_with_email(&pool, "alice@example.com").await;
+        let old_token =
+            issue_verification_token(&pool, &user_id, "alice@example.com", &configuration)
+                .await
+                .unwrap();
+        let new_token =
+            issue_verification_token(&pool, &user_id, "alice@example.com", &configuration)
+                .await
+                .unwrap();
+
+        assert!(is_invalid_token(verify_email(&pool, &old_token).await));
+
+        verify_email(&pool, &new_token).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tokens_for_a_replaced_address_are_rejected() {
+        let pool = testing::database().await;
+        let configuration = testing::configuration().email_verification;
+        let user_id = user_with_email(&pool, "alice@example.com").await;
+        let token = issue_verification_token(&pool, &user_id, "alice@example.com", &configuration)
+            .await
+            .unwrap();
+
+        sqlx::query("UPDATE users SET email = 'alice@example.org' WHERE id = ?;")
+            .bind(&user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(is_invalid_token(verify_email(&pool, &token).await));
+        assert!(email_verified_at(&pool, &user_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_rejected() {
+        let pool = testing::database().await;
+
+        assert!(is_invalid_token(verify_email(&pool, "unknown").await));
+    }
+}
@@ -14,6 +14,40 @@ pub enum AuthenticationError {
     MissingCredentials,
     #[error("access token creation failed")]
     TokenCreation,
+    #[error("insufficient permissions")]
+    Forbidden,
+    #[error("invalid refresh token")]
+    InvalidRefreshToken,
+    #[error("account is disabled")]
+    AccountDisabled,
+    #[error("too many failed sign-in attempts, retry in {retry_after} seconds")]
+    TooManyAttempts { retry_after: u64 },
+    #[error("invalid two-factor challenge")]
+    InvalidChallenge,
+    #[error("invalid two-factor code")]
+    InvalidTwoFactorCode,
+    #[error("two-factor authentication is already enabled")]
+    TwoFactorAlreadyEnabled,
+    #[error("two-factor authentication is not enrolled")]
+    TwoFactorNotEnrolled,
+    #[error("invalid or expired password reset token")]
+    InvalidResetToken,
+    #[error("username is already taken")]
+    UsernameTaken,
+    #[error("email address is already in use")]
+    EmailTaken,
+    #[error("account has no email address")]
+    MissingEmail,
+    #[error("email address is not verified")]
+    EmailNotVerified,
+    #[error("invalid or expired email verification token")]
+    InvalidVerificationToken,
+    #[error("unknown identity provider")]
+    UnknownProvider,
+    #[error("invalid or expired authorization state")]
+    InvalidAuthorizationState,
+    #[error("identity provider request failed")]
+    IdentityProvider,
 }
 
 impl MapToStatusCode for AuthenticationError {
@@ -22,6 +56,23 @@ impl MapToStatusCode for AuthenticationError {
             AuthenticationError::WrongCredentials => StatusCode::UNAUTHORIZED,
             AuthenticationError::MissingCredentials => StatusCode::BAD_REQUEST,
             AuthenticationError::TokenCreation => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthenticationError::Forbidden => StatusCode::FORBIDDEN,
+            AuthenticationError::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
+            AuthenticationError::AccountDisabled => StatusCode::FORBIDDEN,
+            AuthenticationError::TooManyAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AuthenticationError::InvalidChallenge => StatusCode::UNAUTHORIZED,
+            AuthenticationError::InvalidTwoFactorCode => StatusCode::UNAUTHORIZED,
+            AuthenticationError::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+            AuthenticationError::TwoFactorNotEnrolled => StatusCode::BAD_REQUEST,
+            AuthenticationError::InvalidResetToken => StatusCode::BAD_REQUEST,
+            AuthenticationError::UsernameTaken => StatusCode::CONFLICT,
+            AuthenticationError::EmailTaken => StatusCode::CONFLICT,
+            AuthenticationError::MissingEmail => StatusCode::BAD_REQUEST,
+            AuthenticationError::EmailNotVerified => StatusCode::FORBIDDEN,
+            AuthenticationError::InvalidVerificationToken => StatusCode::BAD_REQUEST,
+            AuthenticationError::UnknownProvider => StatusCode::NOT_FOUND,
+            AuthenticationError::InvalidAuthorizationState => StatusCode::BAD_REQUEST,
+            AuthenticationError::IdentityProvider => StatusCode::BAD_GATEWAY,
         }
     }
 }
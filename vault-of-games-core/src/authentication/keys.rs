@@ -1,8 +1,25 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
-use jwt_simple::prelude::{Ed25519KeyPair, Ed25519PublicKey};
-use once_cell::sync::Lazy;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use ct_codecs::{Base64UrlSafeNoPadding, Encoder};
+use jwt_simple::prelude::{
+    Ed25519KeyPair, Ed25519PublicKey, EdDSAKeyPairLike, EdDSAPublicKeyLike, JWTClaims, Token,
+};
+use once_cell::sync::OnceCell;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::tokens::ACCESS_TOKEN_LIFETIME_MINUTES;
+
+pub const DEFAULT_KEYS_DIRECTORY: &str = "keys";
+const LEGACY_KEY_ID: &str = "default";
+const PEM_PREFIX: &str = "-----BEGIN";
+/// Written next to the public key of a retired key, holding the time of its retirement.
+const RETIREMENT_FILE_NAME: &str = "retired_at";
 
 enum KeyType {
     Private,
@@ -22,35 +39,485 @@ impl std::fmt::Display for KeyType {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyFormat {
+    Raw,
+    Pem,
+}
+
 pub struct KeyPair {
-    pub private_key: Ed25519KeyPair,
+    pub key_id: String,
+    /// Retired keys no longer have a private key and are only kept to verify tokens that were
+    /// signed before the rotation.
+    pub private_key: Option<Ed25519KeyPair>,
     pub public_key: Ed25519PublicKey,
 }
 
 impl KeyPair {
-    pub fn new(private_key: Ed25519KeyPair, public_key: Ed25519PublicKey) -> Self {
+    pub fn new(
+        key_id: String,
+        private_key: Option<Ed25519KeyPair>,
+        public_key: Ed25519PublicKey,
+    ) -> Self {
+        let private_key = private_key.map(|private_key| private_key.with_key_id(&key_id));
+        let public_key = public_key.with_key_id(&key_id);
+
         Self {
+            key_id,
             private_key,
             public_key,
         }
     }
 
-    fn from_key_files() -> Result<Self> {
+    fn read_key_file(path: &Path) -> Result<Option<Vec<u8>>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
         let mut buffer = Vec::new();
 
-        File::open(KeyType::Private.to_string())?.read_to_end(&mut buffer)?;
+        File::open(path)?.read_to_end(&mut buffer)?;
+
+        Ok(Some(buffer))
+    }
+
+    fn write_key_file(path: &Path, contents: &[u8], private: bool) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(0o600);
+        }
+
+        #[cfg(not(unix))]
+        let _ = private;
+
+        options
+            .open(path)
+            .with_context(|| format!("Failed to create {}.", path.display()))?
+            .write_all(contents)?;
+
+        Ok(())
+    }
+
+    /// Keys are stored either as raw bytes, which is what `jwt-simple` exports, or as PEM.
+    fn decode_private_key(buffer: &[u8]) -> Result<Ed25519KeyPair> {
+        match std::str::from_utf8(buffer) {
+            Ok(pem) if pem.starts_with(PEM_PREFIX) => Ed25519KeyPair::from_pem(pem),
+            _ => Ed25519KeyPair::from_bytes(buffer),
+        }
+    }
+
+    fn decode_public_key(buffer: &[u8]) -> Result<Ed25519PublicKey> {
+        match std::str::from_utf8(buffer) {
+            Ok(pem) if pem.starts_with(PEM_PREFIX) => Ed25519PublicKey::from_pem(pem),
+            _ => Ed25519PublicKey::from_bytes(buffer),
+        }
+    }
+
+    pub fn generate(key_id: String) -> Self {
+        let private_key = Ed25519KeyPair::generate();
+        let public_key = private_key.public_key();
+
+        Self::new(key_id, Some(private_key), public_key)
+    }
+
+    fn from_key_files(key_id: String, directory: &Path) -> Result<Self> {
+        let private_key = Self::read_key_file(&directory.join(KeyType::Private.to_string()))?
+            .map(|buffer| Self::decode_private_key(&buffer))
+            .transpose()?;
+
+        let public_key = match Self::read_key_file(&directory.join(KeyType::Public.to_string()))? {
+            Some(buffer) => Self::decode_public_key(&buffer)?,
+            None => private_key
+                .as_ref()
+                .map(|private_key| private_key.public_key())
+                .ok_or_else(|| anyhow!("Key {} has neither a private nor a public key.", key_id))?,
+        };
+
+        Ok(Self::new(key_id, private_key, public_key))
+    }
+
+    /// Writes the key into `directory` in the same layout that the key ring reads.
+    pub fn write_key_files(&self, directory: &Path, format: KeyFormat) -> Result<()> {
+        fs::create_dir_all(directory)?;
 
-        let private_key = Ed25519KeyPair::from_bytes(&buffer)?;
+        if let Some(private_key) = &self.private_key {
+            let contents = match format {
+                KeyFormat::Raw => private_key.to_bytes(),
+                KeyFormat::Pem => private_key.to_pem().into_bytes(),
+            };
 
-        buffer.clear();
+            Self::write_key_file(
+                &directory.join(KeyType::Private.to_string()),
+                &contents,
+                true,
+            )?;
+        }
 
-        File::open(KeyType::Public.to_string())?.read_to_end(&mut buffer)?;
+        let contents = match format {
+            KeyFormat::Raw => self.public_key.to_bytes(),
+            KeyFormat::Pem => self.public_key.to_pem().into_bytes(),
+        };
 
-        let public_key = Ed25519PublicKey::from_bytes(&buffer)?;
+        Self::write_key_file(
+            &directory.join(KeyType::Public.to_string()),
+            &contents,
+            false,
+        )
+    }
 
-        Ok(Self::new(private_key, public_key))
+    pub fn to_json_web_key(&self) -> Result<JsonWebKey> {
+        Ok(JsonWebKey {
+            kty: "OKP",
+            crv: "Ed25519",
+            alg: "EdDSA",
+            r#use: "sig",
+            kid: self.key_id.clone(),
+            x: Base64UrlSafeNoPadding::encode_to_string(self.public_key.to_bytes())?,
+        })
     }
 }
 
-pub static LAZY_KEYPAIR: Lazy<KeyPair> =
-    Lazy::new(|| KeyPair::from_key_files().expect("Failed to read key files."));
+#[derive(Debug, Serialize)]
+pub struct JsonWebKey {
+    kty: &'static str,
+    crv: &'static str,
+    alg: &'static str,
+    r#use: &'static str,
+    kid: String,
+    x: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonWebKeySet {
+    keys: Vec<JsonWebKey>,
+}
+
+/// All keys known to the server, indexed by their key IDs.
+///
+/// Every key lives in its own `<keys directory>/<key ID>/` subdirectory holding `private.key` and
+/// `public.key`. Tokens are signed with the newest key that still has a private key, unless
+/// `SIGNING_KEY_ID` says otherwise. Retiring a key means deleting its `private.key`; its
+/// `public.key` can be removed once the tokens signed with it have expired.
+///
+/// A directory holding `private.key` and `public.key` directly is read as a single key.
+pub struct KeyRing {
+    keys: Vec<KeyPair>,
+    signing_key_index: usize,
+}
+
+impl KeyRing {
+    pub fn from_directory(directory: &Path, signing_key_id: Option<&str>) -> Result<Self> {
+        if directory.join(KeyType::Private.to_string()).exists() {
+            return Ok(Self {
+                keys: vec![KeyPair::from_key_files(
+                    LEGACY_KEY_ID.to_string(),
+                    directory,
+                )?],
+                signing_key_index: 0,
+            });
+        }
+
+        let mut key_directories = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .collect::<Vec<(String, PathBuf)>>();
+
+        key_directories.sort();
+
+        let keys = key_directories
+            .into_iter()
+            .map(|(key_id, path)| KeyPair::from_key_files(key_id, &path))
+            .collect::<Result<Vec<KeyPair>>>()?;
+
+        let signing_key_index = match signing_key_id {
+            Some(signing_key_id) => keys
+                .iter()
+                .position(|key| key.key_id == signing_key_id && key.private_key.is_some()),
+            None => keys.iter().rposition(|key| key.private_key.is_some()),
+        }
+        .ok_or_else(|| anyhow!("No signing key found in {}.", directory.display()))?;
+
+        Ok(Self {
+            keys,
+            signing_key_index,
+        })
+    }
+
+    fn from_environment() -> Result<Self> {
+        let directory = std::env::var("KEYS_DIRECTORY").unwrap_or_else(|_| {
+            if Path::new(DEFAULT_KEYS_DIRECTORY).is_dir() {
+                DEFAULT_KEYS_DIRECTORY.to_string()
+            } else {
+                ".".to_string()
+            }
+        });
+        let signing_key_id = std::env::var("SIGNING_KEY_ID").ok();
+
+        Self::load(Path::new(&directory), signing_key_id.as_deref())
+    }
+
+    /// Reads the key ring that the server signs with, telling how to create a key if there is
+    /// none to read.
+    pub fn load(directory: &Path, signing_key_id: Option<&str>) -> Result<Self> {
+        Self::from_directory(directory, signing_key_id).context(
+            "Failed to read key files, run `vault-of-games-core keys generate` to create a signing key",
+        )
+    }
+
+    pub fn signing_key(&self) -> &KeyPair {
+        &self.keys[self.signing_key_index]
+    }
+
+    pub fn find(&self, key_id: &str) -> Option<&KeyPair> {
+        self.keys.iter().find(|key| key.key_id == key_id)
+    }
+
+    pub fn sign<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        claims: JWTClaims<CustomClaims>,
+    ) -> Result<String> {
+        self.signing_key()
+            .private_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Signing key has no private key."))?
+            .sign(claims)
+    }
+
+    /// Verifies a token with the public key named by its `kid` header.
+    pub fn verify<CustomClaims: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<JWTClaims<CustomClaims>> {
+        let metadata = Token::decode_metadata(token)?;
+
+        let key = match metadata.key_id() {
+            Some(key_id) => self
+                .find(key_id)
+                .ok_or_else(|| anyhow!("Unknown key ID {}.", key_id))?,
+            None => self.signing_key(),
+        };
+
+        key.public_key.verify_token::<CustomClaims>(token, None)
+    }
+
+    pub fn to_json_web_key_set(&self) -> Result<JsonWebKeySet> {
+        Ok(JsonWebKeySet {
+            keys: self
+                .keys
+                .iter()
+                .map(KeyPair::to_json_web_key)
+                .collect::<Result<Vec<JsonWebKey>>>()?,
+        })
+    }
+}
+
+/// Directory that new keys are written to, `keys` unless `KEYS_DIRECTORY` is set.
+pub fn keys_directory() -> PathBuf {
+    std::env::var("KEYS_DIRECTORY")
+        .unwrap_or_else(|_| DEFAULT_KEYS_DIRECTORY.to_string())
+        .into()
+}
+
+/// Generates a new key in `directory`, named after the current time so that it sorts after every
+/// older key.
+pub fn generate_key(directory: &Path, format: KeyFormat) -> Result<KeyPair> {
+    let key_id = Utc::now().format("%Y%m%d%H%M%S").to_string();
+    let directory = directory.join(&key_id);
+
+    if directory.exists() {
+        return Err(anyhow!("Key {} already exists.", key_id));
+    }
+
+    let key = KeyPair::generate(key_id);
+
+    key.write_key_files(&directory, format)?;
+
+    Ok(key)
+}
+
+/// Generates a new signing key and retires all the others.
+///
+/// Keys that were retired long enough ago for all of their tokens to have expired are removed.
+pub fn rotate_keys(directory: &Path, format: KeyFormat) -> Result<KeyPair> {
+    let key = generate_key(directory, format)?;
+    let now = Utc::now();
+    let retention = Duration::minutes(ACCESS_TOKEN_LIFETIME_MINUTES as i64);
+
+    for entry in fs::read_dir(directory)? {
+        let directory = entry?.path();
+
+        if !directory.is_dir() || directory.ends_with(&key.key_id) {
+            continue;
+        }
+
+        let private_key = directory.join(KeyType::Private.to_string());
+        let retirement = directory.join(RETIREMENT_FILE_NAME);
+
+        if private_key.exists() {
+            fs::remove_file(private_key)?;
+            fs::write(retirement, now.to_rfc3339())?;
+        } else if let Ok(retired_at) = fs::read_to_string(&retirement) {
+            let retired_at = DateTime::parse_from_rfc3339(retired_at.trim())?.with_timezone(&Utc);
+
+            if retired_at + retention < now {
+                fs::remove_dir_all(directory)?;
+            }
+        }
+    }
+
+    Ok(key)
+}
+
+static KEY_RING: OnceCell<KeyRing> = OnceCell::new();
+
+/// Loads the key ring, so that missing or malformed keys are reported at startup.
+pub fn initialize_key_ring() -> Result<()> {
+    let key_ring = KeyRing::from_environment()?;
+
+    KEY_RING
+        .set(key_ring)
+        .map_err(|_| anyhow!("Key ring is already initialized."))
+}
+
+/// Signs with a key that is generated on first use, so that tests need no key files.
+#[cfg(test)]
+pub fn initialize_test_key_ring() {
+    KEY_RING.get_or_init(|| KeyRing {
+        keys: vec![KeyPair::generate("test".to_string())],
+        signing_key_index: 0,
+    });
+}
+
+pub fn key_ring() -> &'static KeyRing {
+    KEY_RING.get().expect("Key ring is not initialized.")
+}
+
+#[cfg(test)]
+mod tests {
+    use jwt_simple::prelude::{Claims, Duration as JwtDuration, NoCustomClaims};
+
+    use super::*;
+    use crate::testing::TemporaryDirectory;
+
+    const OLD_KEY_ID: &str = "20200101000000";
+
+    fn token(key_ring: &KeyRing) -> String {
+        key_ring
+            .sign(Claims::create(JwtDuration::from_mins(1)))
+            .unwrap()
+    }
+
+    /// Reads a key ring from a directory holding a single key, which is then rotated.
+    fn rotated_key_ring() -> (TemporaryDirectory, String, String) {
+        let directory = TemporaryDirectory::new();
+
+        KeyPair::generate(OLD_KEY_ID.to_string())
+            .write_key_files(&directory.join(OLD_KEY_ID), KeyFormat::Raw)
+            .unwrap();
+
+        let old_token = token(&KeyRing::from_directory(&directory, None).unwrap());
+        let new_key_id = rotate_keys(&directory, KeyFormat::Raw).unwrap().key_id;
+
+        (directory, old_token, new_key_id)
+    }
+
+    #[test]
+    fn tokens_name_their_signing_key() {
+        let key_ring = KeyRing {
+            keys: vec![
+                KeyPair::generate("first".to_string()),
+                KeyPair::generate("second".to_string()),
+            ],
+            signing_key_index: 1,
+        };
+
+        let token = token(&key_ring);
+
+        assert_eq!(
+            Token::decode_metadata(&token).unwrap().key_id(),
+            Some("second")
+        );
+        assert!(key_ring.verify::<NoCustomClaims>(&token).is_ok());
+    }
+
+    #[test]
+    fn tokens_signed_before_a_rotation_still_verify() {
+        let (directory, old_token, new_key_id) = rotated_key_ring();
+        let key_ring = KeyRing::from_directory(&directory, None).unwrap();
+
+        assert_eq!(key_ring.signing_key().key_id, new_key_id);
+        assert!(key_ring.find(OLD_KEY_ID).unwrap().private_key.is_none());
+        assert!(key_ring.verify::<NoCustomClaims>(&old_token).is_ok());
+        assert_eq!(
+            Token::decode_metadata(&token(&key_ring)).unwrap().key_id(),
+            Some(new_key_id.as_str())
+        );
+    }
+
+    #[test]
+    fn unknown_key_ids_are_rejected() {
+        let key_ring = KeyRing {
+            keys: vec![KeyPair::generate("known".to_string())],
+            signing_key_index: 0,
+        };
+        let foreign_key_ring = KeyRing {
+            keys: vec![KeyPair::generate("unknown".to_string())],
+            signing_key_index: 0,
+        };
+
+        assert!(key_ring
+            .verify::<NoCustomClaims>(&token(&foreign_key_ring))
+            .is_err());
+    }
+
+    #[test]
+    fn key_sets_hold_only_public_keys() {
+        let (directory, _, new_key_id) = rotated_key_ring();
+        let key_ring = KeyRing::from_directory(&directory, None).unwrap();
+
+        let key_set = serde_json::to_value(key_ring.to_json_web_key_set().unwrap()).unwrap();
+        let keys = key_set["keys"].as_array().unwrap();
+        let key_ids = keys
+            .iter()
+            .map(|key| key["kid"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+
+        assert_eq!(key_ids, vec![OLD_KEY_ID, new_key_id.as_str()]);
+
+        for key in keys {
+            let mut members = key
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<&str>>();
+
+            members.sort_unstable();
+
+            assert_eq!(members, vec!["alg", "crv", "kid", "kty", "use", "x"]);
+            assert_eq!(
+                key["x"].as_str().unwrap(),
+                Base64UrlSafeNoPadding::encode_to_string(
+                    key_ring
+                        .find(key["kid"].as_str().unwrap())
+                        .unwrap()
+                        .public_key
+                        .to_bytes()
+                )
+                .unwrap()
+            );
+        }
+    }
+}
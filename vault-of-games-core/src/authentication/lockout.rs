@@ -0,0 +1,415 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::query;
+
+use crate::{
+    configuration::LockoutConfiguration, database::DatabaseConnectionPool, error::ProcessorError,
+};
+
+use super::error::AuthenticationError;
+
+/// Subject that failed sign-in attempts are counted against.
+pub enum LockoutKey<'a> {
+    Username(&'a str),
+    Address(IpAddr),
+}
+
+impl std::fmt::Display for LockoutKey<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockoutKey::Username(username) => write!(f, "username:{}", username),
+            LockoutKey::Address(address) => write!(f, "address:{}", address),
+        }
+    }
+}
+
+/// Lockout that follows the given number of consecutive failures, growing exponentially once the
+/// threshold is reached.
+pub fn lockout_duration(failures: u32, configuration: &LockoutConfiguration) -> Option<Duration> {
+    if failures < configuration.threshold {
+        return None;
+    }
+
+    let exponent = (failures - configuration.threshold).min(32);
+    let seconds = configuration
+        .base_duration_seconds
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(configuration.max_duration_seconds);
+
+    Some(Duration::seconds(seconds as i64))
+}
+
+/// Counts a sign-in attempt against every key before the credentials are checked, and rejects
+/// it with the remaining lockout time if any of the keys is locked out. Attempts that succeed
+/// are taken back with [`forgive_attempt`] or [`clear_failures`].
+///
+/// The first statement of the transaction writes, which makes SQLite serialize concurrent
+/// attempts, so that they cannot all pass the check before any of them is counted.
+///
+/// Since attempts are counted for any username, rows whose failures have left the window and
+/// whose lockout has ended are removed along the way.
+pub async fn begin_attempt(
+    pool: &DatabaseConnectionPool,
+    keys: &[LockoutKey<'_>],
+    now: DateTime<Utc>,
+    configuration: &LockoutConfiguration,
+) -> Result<(), ProcessorError> {
+    let window_start = now - Duration::seconds(configuration.window_seconds as i64);
+    let mut transaction = pool.begin().await?;
+
+    query!(
+        "
+        DELETE
+        FROM sign_in_failures
+        WHERE last_failure_at <= ?1 AND (locked_until IS NULL OR locked_until <= ?2);
+        ",
+        window_start,
+        now,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    for key in keys {
+        let key = key.to_string();
+
+        // Failures older than the window start the count over.
+        let attempt = query!(
+            "
+            INSERT INTO sign_in_failures (key, failures, locked_until, last_failure_at)
+            VALUES (?1, 1, NULL, ?2)
+            ON CONFLICT (key) DO UPDATE SET failures        = CASE
+                                                                  WHEN last_failure_at > ?3
+                                                                      THEN failures + 1
+                                                                  ELSE 1 END,
+                                            last_failure_at = excluded.last_failure_at
+            WHERE locked_until IS NULL OR locked_until <= ?2;
+            ",
+            key,
+            now,
+            window_start,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        let record = query!(
+            r#"
+            SELECT failures as "failures!: u32",
+                locked_until as "locked_until?: DateTime<Utc>"
+            FROM sign_in_failures
+            WHERE key = ?;
+            "#,
+            key
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+
+        // Nothing was counted, so the key is locked out. Dropping the transaction takes back the
+        // counts of the keys before it.
+        if attempt.rows_affected() == 0 {
+            let locked_until = record.locked_until.unwrap_or(now);
+            let retry_after = (locked_until - now).num_seconds().max(1) as u64;
+
+            return Err(AuthenticationError::TooManyAttempts { retry_after }.into());
+        }
+
+        let locked_until =
+            lockout_duration(record.failures, configuration).map(|duration| now + duration);
+
+        query!(
+            "
+            UPDATE sign_in_failures
+            SET locked_until = ?1
+            WHERE key = ?2;
+            ",
+            locked_until,
+            key,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Takes back an attempt counted by [`begin_attempt`] that succeeded, lifting the lockout that it
+/// may have caused. Failures of others behind the same key still count.
+pub async fn forgive_attempt(
+    pool: &DatabaseConnectionPool,
+    key: &LockoutKey<'_>,
+    configuration: &LockoutConfiguration,
+) -> Result<(), ProcessorError> {
+    let key = key.to_string();
+
+    query!(
+        "
+        UPDATE sign_in_failures
+        SET failures     = failures - 1,
+            locked_until = CASE WHEN failures - 1 < ?1 THEN NULL ELSE locked_until END
+        WHERE key = ?2 AND failures > 0;
+        ",
+        configuration.threshold,
+        key,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn clear_failures(
+    pool: &DatabaseConnectionPool,
+    key: &LockoutKey<'_>,
+) -> Result<u64, ProcessorError> {
+    let key = key.to_string();
+
+    let result = query!(
+        "
+        DELETE
+        FROM sign_in_failures
+        WHERE key = ?;
+        ",
+        key
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use chrono::TimeZone;
+
+    use crate::testing;
+
+    use super::*;
+
+    fn configuration() -> LockoutConfiguration {
+        LockoutConfiguration {
+            threshold: 3,
+            base_duration_seconds: 30,
+            max_duration_seconds: 120,
+            window_seconds: 900,
+        }
+    }
+
+    fn retry_after(result: Result<(), ProcessorError>) -> Option<u64> {
+        match result {
+            Err(ProcessorError::AuthenticationError(AuthenticationError::TooManyAttempts {
+                retry_after,
+            })) => Some(retry_after),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(()) => None,
+        }
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let configuration = configuration();
+
+        assert_eq!(lockout_duration(2, &configuration), None);
+        assert_eq!(
+            lockout_duration(3, &configuration),
+            Some(Duration::seconds(30))
+        );
+        assert_eq!(
+            lockout_duration(4, &configuration),
+            Some(Duration::seconds(60))
+        );
+        assert_eq!(
+            lockout_duration(5, &configuration),
+            Some(Duration::seconds(120))
+        );
+        assert_eq!(
+            lockout_duration(50, &configuration),
+            Some(Duration::seconds(120))
+        );
+    }
+
+    #[tokio::test]
+    async fn attempts_beyond_the_threshold_are_locked_out_until_the_lockout_ends() {
+        let pool = testing::database().await;
+        let configuration = configuration();
+        let keys = [LockoutKey::Username("alice")];
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(
+                retry_after(begin_attempt(&pool, &keys, start, &configuration).await),
+                None
+            );
+        }
+
+        let later = start + Duration::seconds(10);
+
+        assert_eq!(
+            retry_after(begin_attempt(&pool, &keys, later, &configuration).await),
+            Some(20)
+        );
+
+        // The rejected attempt was not counted, so the next lockout is only twice as long.
+        let after_lockout = start + Duration::seconds(31);
+
+        assert_eq!(
+            retry_after(begin_attempt(&pool, &keys, after_lockout, &configuration).await),
+            None
+        );
+        assert_eq!(
+            retry_after(begin_attempt(&pool, &keys, after_lockout, &configuration).await),
+            Some(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_outside_of_the_window_are_forgotten() {
+        let pool = testing::database().await;
+        let configuration = configuration();
+        let keys = [LockoutKey::Username("alice")];
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+
+        for minute in 0..2 {
+            let now = start + Duration::minutes(minute);
+
+            assert_eq!(
+                retry_after(begin_attempt(&pool, &keys, now, &configuration).await),
+                None
+            );
+        }
+
+        let much_later = start + Duration::seconds(configuration.window_seconds as i64 + 120);
+
+        for _ in 0..2 {
+            assert_eq!(
+                retry_after(begin_attempt(&pool, &keys, much_later, &configuration).await),
+                None
+            );
+        }
+    }
+
+    async fn failure_keys(pool: &DatabaseConnectionPool) -> Vec<String> {
+        sqlx::query_scalar("SELECT key FROM sign_in_failures ORDER BY key;")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn stale_failures_are_removed() {
+        let pool = testing::database().await;
+        let configuration = LockoutConfiguration {
+            base_duration_seconds: 120,
+            window_seconds: 60,
+            ..configuration()
+        };
+        let start = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+
+        begin_attempt(
+            &pool,
+            &[LockoutKey::Username("alice")],
+            start,
+            &configuration,
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..3 {
+            begin_attempt(&pool, &[LockoutKey::Username("bob")], start, &configuration)
+                .await
+                .unwrap();
+        }
+
+        let later = start + Duration::seconds(90);
+
+        begin_attempt(
+            &pool,
+            &[LockoutKey::Username("carol")],
+            later,
+            &configuration,
+        )
+        .await
+        .unwrap();
+
+        // Bob is still locked out, even though his failures have left the window.
+        assert_eq!(
+            failure_keys(&pool).await,
+            vec!["username:bob", "username:carol"]
+        );
+
+        let after_lockout = start + Duration::seconds(121);
+
+        begin_attempt(
+            &pool,
+            &[LockoutKey::Username("carol")],
+            after_lockout,
+            &configuration,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(failure_keys(&pool).await, vec!["username:carol"]);
+    }
+
+    /// Runs against a database file shared by several connections, since the single connection
+    /// of [`testing::database`] would serialize the attempts on its own.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_attempts_cannot_exceed_the_threshold() {
+        let directory = testing::TemporaryDirectory::new();
+        let pool = testing::shared_database(&directory).await;
+        let configuration = configuration();
+        let now = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+
+        let attempts = (0..10).map(|_| {
+            let pool = pool.clone();
+            let configuration = configuration.clone();
+
+            tokio::spawn(async move {
+                begin_attempt(&pool, &[LockoutKey::Username("alice")], now, &configuration)
+                    .await
+                    .is_ok()
+            })
+        });
+
+        let mut admitted = 0;
+
+        for attempt in attempts.collect::<Vec<_>>() {
+            if attempt.await.unwrap() {
+                admitted += 1;
+            }
+        }
+
+        assert_eq!(admitted, configuration.threshold);
+    }
+
+    #[tokio::test]
+    async fn forgiven_attempts_lift_the_lockout_they_caused() {
+        let pool = testing::database().await;
+        let configuration = configuration();
+        let address = LockoutKey::Address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let keys = [LockoutKey::Address(IpAddr::V4(Ipv4Addr::LOCALHOST))];
+        let now = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+
+        for _ in 0..3 {
+            begin_attempt(&pool, &keys, now, &configuration)
+                .await
+                .unwrap();
+        }
+
+        forgive_attempt(&pool, &address, &configuration)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            retry_after(begin_attempt(&pool, &keys, now, &configuration).await),
+            None
+        );
+        assert_eq!(
+            retry_after(begin_attempt(&pool, &keys, now, &configuration).await),
+            Some(30)
+        );
+    }
+}
@@ -2,41 +2,123 @@ use axum::{
     body::{boxed, Body, BoxBody},
     http::Response,
 };
-use futures::future::BoxFuture;
+use futures::future::{ready, BoxFuture, Ready};
 use hyper::{
     header::{AUTHORIZATION, CONTENT_TYPE},
     Request, StatusCode,
 };
-use jwt_simple::{
-    prelude::{EdDSAPublicKeyLike, JWTClaims, NoCustomClaims},
-    reexports::coarsetime::Duration,
-};
+use jwt_simple::reexports::coarsetime::Duration;
 use serde_json::json;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tower_http::auth::AsyncAuthorizeRequest;
 
-use super::keys::LAZY_KEYPAIR;
+use crate::{
+    audit::{AuditAction, AuditRecord},
+    configuration::{Configuration, TokenTransport},
+    database::DatabaseConnectionPool,
+};
 
-#[derive(Clone, Copy)]
-pub struct JWTAuthorizationLayer;
+use super::{
+    claims::{AccessTokenClaims, AuthorizationClaims},
+    cookies::CookieCredentials,
+    keys::key_ring,
+    personal_access_tokens::{self, PERSONAL_ACCESS_TOKEN_PREFIX},
+    revocation,
+    role::Role,
+    scope::{Scope, ScopeRequirement},
+    sessions::{ClientMetadata, LAST_SEEN},
+};
+
+/// Authorizes requests bearing a signed access token, in the `Authorization` header or a cookie as
+/// the configured [`TokenTransport`] allows. Cookies only authorize requests that may change
+/// state along with a matching CSRF token.
+///
+/// Personal access tokens are only accepted in the `Authorization` header, whatever the transport,
+/// and only on routes that declare the scopes they require through
+/// [`JWTAuthorizationLayer::with_scopes`].
+#[derive(Clone, Copy, Default)]
+pub struct JWTAuthorizationLayer {
+    scopes: Option<ScopeRequirement>,
+}
+
+impl JWTAuthorizationLayer {
+    pub fn with_scopes(read: Scope, write: Scope) -> Self {
+        Self {
+            scopes: Some(ScopeRequirement::new(read, write)),
+        }
+    }
+}
 
 impl AsyncAuthorizeRequest for JWTAuthorizationLayer {
-    type Output = JWTClaims<NoCustomClaims>;
-    type Future = BoxFuture<'static, Option<JWTClaims<NoCustomClaims>>>;
+    type Output = AuthorizationClaims;
+    type Future = BoxFuture<'static, Option<AuthorizationClaims>>;
     type ResponseBody = BoxBody;
 
     fn authorize<B>(&mut self, request: &Request<B>) -> Self::Future {
-        let claims = request
+        let transport = request
+            .extensions()
+            .get::<Arc<Configuration>>()
+            .map(|configuration| configuration.cookies.transport)
+            .unwrap_or(TokenTransport::Header);
+
+        // Personal access tokens are meant for scripts, which have no cookies to send, so they
+        // are taken from the header whatever the transport.
+        let bearer = request
             .headers()
             .get(AUTHORIZATION)
             .and_then(|header_value| header_value.to_str().ok())
             .and_then(|bearer| bearer.strip_prefix("Bearer "))
-            .map(|token| {
-                LAZY_KEYPAIR
-                    .public_key
-                    .verify_token::<NoCustomClaims>(token, None)
-                    .ok()
+            .filter(|bearer| {
+                transport.accepts_header() || bearer.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX)
             })
+            .map(str::to_string);
+
+        let token = bearer.or_else(|| {
+            if !transport.accepts_cookie() {
+                return None;
+            }
+
+            let credentials = CookieCredentials::from_headers(request.headers());
+
+            // Personal access tokens are never handed out as cookies.
+            credentials
+                .access_token
+                .clone()
+                .filter(|token| !token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX))
+                .filter(|_| credentials.passes_csrf_check(request.method()))
+        });
+
+        let pool = request
+            .extensions()
+            .get::<DatabaseConnectionPool>()
+            .cloned();
+
+        if let Some(token) = token
+            .as_deref()
+            .filter(|token| token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX))
+        {
+            let token = token.to_string();
+            let scope = self
+                .scopes
+                .map(|scopes| scopes.for_method(request.method()));
+
+            return Box::pin(async move {
+                match personal_access_tokens::authorize(&pool?, &token, scope?).await {
+                    Ok(claims) => claims,
+                    Err(error) => {
+                        tracing::error!("{}", error);
+
+                        None
+                    }
+                }
+            });
+        }
+
+        let claims = token
+            .map(|token| key_ring().verify::<AccessTokenClaims>(&token).ok())
             .unwrap_or(None)
             .filter(|claims| {
                 let expiration = claims.expires_at.unwrap();
@@ -50,10 +132,44 @@ impl AsyncAuthorizeRequest for JWTAuthorizationLayer {
                 expiration > now
             });
 
-        Box::pin(async move { claims })
+        let client = ClientMetadata::of_request(request);
+
+        Box::pin(async move {
+            let claims = claims?;
+            let pool = pool?;
+
+            match revocation::is_revoked(&pool, &claims).await {
+                Ok(false) => {
+                    LAST_SEEN.touch(&claims.custom.session_id);
+
+                    Some(claims)
+                }
+                Ok(true) => {
+                    let mut record = AuditRecord::new(AuditAction::RevokedTokenUsed)
+                        .with_target(&claims.custom.session_id);
+
+                    if let Some(subject) = &claims.subject {
+                        record = record.with_actor(subject);
+                    }
+
+                    if let Some(client) = &client {
+                        record = record.with_client(client);
+                    }
+
+                    record.record(&pool).await;
+
+                    None
+                }
+                Err(error) => {
+                    tracing::error!("{}", error);
+
+                    None
+                }
+            }
+        })
     }
 
-    fn on_authorized<B>(&mut self, request: &mut Request<B>, claims: JWTClaims<NoCustomClaims>) {
+    fn on_authorized<B>(&mut self, request: &mut Request<B>, claims: AuthorizationClaims) {
         request.extensions_mut().insert(claims);
     }
 
@@ -62,8 +178,262 @@ impl AsyncAuthorizeRequest for JWTAuthorizationLayer {
             .status(StatusCode::UNAUTHORIZED)
             .header(CONTENT_TYPE, "application/json")
             .body(boxed(Body::from(
-                json!({ "message": "Please provide a valid Bearer token in Authorization header." }).to_string(),
+                json!({ "message": "Please provide a valid Bearer token in Authorization header or a session cookie." }).to_string(),
+            )))
+            .unwrap()
+    }
+}
+
+/// Requires the claims inserted by [`JWTAuthorizationLayer`] to carry at least the given role, so
+/// it has to be layered inside of it.
+#[derive(Clone, Copy)]
+pub struct RoleAuthorizationLayer {
+    required_role: Role,
+}
+
+impl RoleAuthorizationLayer {
+    pub fn new(required_role: Role) -> Self {
+        Self { required_role }
+    }
+}
+
+impl AsyncAuthorizeRequest for RoleAuthorizationLayer {
+    type Output = ();
+    type Future = Ready<Option<()>>;
+    type ResponseBody = BoxBody;
+
+    fn authorize<B>(&mut self, request: &Request<B>) -> Self::Future {
+        let authorized = request
+            .extensions()
+            .get::<AuthorizationClaims>()
+            .map(|claims| claims.custom.role >= self.required_role)
+            .unwrap_or(false);
+
+        ready(authorized.then_some(()))
+    }
+
+    fn unauthorized_response<B>(&mut self, _request: &Request<B>) -> Response<BoxBody> {
+        Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header(CONTENT_TYPE, "application/json")
+            .body(boxed(Body::from(
+                json!({ "message": "insufficient permissions" }).to_string(),
             )))
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::SET_COOKIE, Method};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        authentication::{cookies::ACCESS_TOKEN_COOKIE, tokens},
+        configuration::CookieConfiguration,
+        testing::{self, TestApp},
+    };
+
+    async fn create_personal_access_token(
+        app: &TestApp,
+        access_token: &str,
+        scopes: Value,
+    ) -> String {
+        let response = app
+            .request(
+                Method::POST,
+                "/v1/users/tokens",
+                Some(access_token),
+                Some(json!({ "name": "script", "scopes": scopes })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        response.body["token"].as_str().unwrap().to_string()
+    }
+
+    async fn create_game(app: &TestApp, token: &str) -> StatusCode {
+        app.request(
+            Method::POST,
+            "/v1/games",
+            Some(token),
+            Some(json!({ "title": "Hades" })),
+        )
+        .await
+        .status
+    }
+
+    #[tokio::test]
+    async fn personal_access_tokens_are_limited_to_their_scopes() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let read_token =
+            create_personal_access_token(&app, &access_token, json!(["games:read"])).await;
+        let write_token =
+            create_personal_access_token(&app, &access_token, json!(["games:read", "games:write"]))
+                .await;
+
+        assert_eq!(create_game(&app, &access_token).await, StatusCode::CREATED);
+
+        let game = app
+            .request(Method::GET, "/v1/games", Some(&read_token), None)
+            .await;
+
+        assert_eq!(game.status, StatusCode::OK);
+
+        let uri = format!(
+            "/v1/games/{}",
+            game.body["items"][0]["id"].as_str().unwrap()
+        );
+
+        assert_eq!(
+            app.request(Method::GET, &uri, Some(&read_token), None)
+                .await
+                .status,
+            StatusCode::OK
+        );
+        assert_eq!(
+            create_game(&app, &read_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            app.request(
+                Method::PATCH,
+                &uri,
+                Some(&read_token),
+                Some(json!({ "title": "Hades II" }))
+            )
+            .await
+            .status,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            app.request(Method::DELETE, &uri, Some(&read_token), None)
+                .await
+                .status,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Writing takes the write scope.
+        assert_eq!(create_game(&app, &write_token).await, StatusCode::CREATED);
+        assert_eq!(
+            app.request(Method::DELETE, &uri, Some(&write_token), None)
+                .await
+                .status,
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[tokio::test]
+    async fn personal_access_tokens_only_work_where_scopes_are_declared() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user_with_role("alice", "admin").await;
+        let token =
+            create_personal_access_token(&app, &access_token, json!(["games:read", "games:write"]))
+                .await;
+
+        for uri in ["/v1/users/me", "/v1/users/tokens", "/v1/admin/users"] {
+            assert_eq!(
+                app.request(Method::GET, uri, Some(&token), None)
+                    .await
+                    .status,
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                uri
+            );
+        }
+
+        assert_eq!(
+            app.request(
+                Method::POST,
+                "/v1/users/sign-out/everywhere",
+                Some(&token),
+                None
+            )
+            .await
+            .status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_personal_access_tokens_are_refused() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let token = create_personal_access_token(&app, &access_token, json!(["games:read"])).await;
+
+        assert_eq!(
+            app.request(Method::GET, "/v1/games", Some(&token), None)
+                .await
+                .status,
+            StatusCode::OK
+        );
+
+        sqlx::query("UPDATE personal_access_tokens SET expires_at = ?;")
+            .bind(chrono::Utc::now() - chrono::Duration::minutes(1))
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            app.request(Method::GET, "/v1/games", Some(&token), None)
+                .await
+                .status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn personal_access_tokens_are_taken_from_the_header_in_cookie_mode() {
+        let app = TestApp::with_configuration(Configuration {
+            cookies: CookieConfiguration {
+                transport: TokenTransport::Cookie,
+                ..testing::configuration().cookies
+            },
+            ..testing::configuration()
+        })
+        .await;
+        let user_id = app.sign_up("alice").await;
+        let token = personal_access_tokens::generate_personal_access_token().unwrap();
+
+        sqlx::query(
+            "
+            INSERT INTO personal_access_tokens (id, user_id, name, token_hash, scopes, created_at)
+            VALUES ('script', ?1, 'script', ?2, '[\"games:read\"]', CURRENT_TIMESTAMP);
+            ",
+        )
+        .bind(&user_id)
+        .bind(tokens::hash_opaque_token(&token).unwrap())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            app.request(Method::GET, "/v1/games", Some(&token), None)
+                .await
+                .status,
+            StatusCode::OK
+        );
+
+        // Signed access tokens still only come in cookies.
+        let sign_in = app.sign_in("alice").await;
+        let access_token = sign_in
+            .headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|header_value| header_value.to_str().ok())
+            .find_map(|cookie| cookie.strip_prefix(&format!("{}=", ACCESS_TOKEN_COOKIE)))
+            .and_then(|cookie| cookie.split(';').next())
+            .unwrap()
+            .to_string();
+
+        assert_eq!(
+            app.request(Method::GET, "/v1/games", Some(&access_token), None)
+                .await
+                .status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
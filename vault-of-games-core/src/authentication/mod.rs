@@ -1,20 +1,91 @@
+pub mod account_deletion;
+pub mod claims;
+pub mod cookies;
+pub mod email_verification;
 pub mod error;
 pub mod keys;
+pub mod lockout;
 pub mod middleware;
+pub mod oidc;
+pub mod password;
+pub mod password_reset;
+pub mod personal_access_tokens;
+pub mod revocation;
+pub mod role;
+pub mod scope;
+pub mod sessions;
+pub mod tokens;
+pub mod two_factor;
 
 use serde::Serialize;
 
+use self::{claims::AuthorizationClaims, error::AuthenticationError, role::Role};
+
 #[derive(Debug, Serialize)]
 pub struct AuthenticationResponse {
     access_token: String,
     token_type: String,
+    expires_in: u64,
+    refresh_token: String,
 }
 
 impl AuthenticationResponse {
-    pub fn new(access_token: String) -> Self {
+    pub fn new(access_token: String, refresh_token: String, expires_in: u64) -> Self {
         Self {
             access_token,
             token_type: "Bearer".to_string(),
+            expires_in,
+            refresh_token,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallengeResponse {
+    challenge_token: String,
+    expires_in: u64,
+}
+
+impl TwoFactorChallengeResponse {
+    pub fn new(challenge_token: String, expires_in: u64) -> Self {
+        Self {
+            challenge_token,
+            expires_in,
         }
     }
 }
+
+/// Returned instead of the tokens when they are only handed out as cookies.
+#[derive(Debug, Serialize)]
+pub struct CookieSessionResponse {
+    csrf_token: String,
+    expires_in: u64,
+}
+
+impl CookieSessionResponse {
+    pub fn new(csrf_token: String, expires_in: u64) -> Self {
+        Self {
+            csrf_token,
+            expires_in,
+        }
+    }
+}
+
+/// Sign-in either completes right away or, with two-factor authentication enabled, asks for a
+/// code first.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SignInResponse {
+    Authenticated(AuthenticationResponse),
+    CookieAuthenticated(CookieSessionResponse),
+    TwoFactorRequired(TwoFactorChallengeResponse),
+}
+
+/// Ensures that the token bearer is the owner of the account identified by `id`, or an admin.
+pub fn authorize_owner(claims: &AuthorizationClaims, id: &str) -> Result<(), AuthenticationError> {
+    match claims.subject.as_deref() {
+        Some(subject) if subject == id => Ok(()),
+        _ if claims.custom.role == Role::Admin => Ok(()),
+        _ => Err(AuthenticationError::Forbidden),
+    }
+}
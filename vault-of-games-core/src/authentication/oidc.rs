@@ -0,0 +1,621 @@
+use std::{collections::HashSet, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Utc};
+use ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use jwt_simple::prelude::{
+    ECDSAP256PublicKeyLike, ES256PublicKey, JWTClaims, RS256PublicKey, RSAPublicKeyLike, Token,
+    VerificationOptions,
+};
+use once_cell::sync::Lazy;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{OidcProviderConfiguration, PasswordConfiguration},
+    database::DatabaseConnectionPool,
+    error::ProcessorError,
+};
+
+use super::{error::AuthenticationError, password, tokens};
+
+/// How long a user may take to authenticate with the identity provider.
+pub const AUTHORIZATION_LIFETIME_MINUTES: i64 = 10;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(StdDuration::from_secs(10))
+        .build()
+        .expect("Failed to build the HTTP client.")
+});
+
+/// The parts of the provider's discovery document that the authorization code flow relies on.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderJsonWebKey {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderJsonWebKeySet {
+    keys: Vec<ProviderJsonWebKey>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct IdTokenClaims {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    preferred_username: Option<String>,
+}
+
+/// User as asserted by the identity provider's ID token.
+#[derive(Debug)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+async fn discover(
+    provider: &OidcProviderConfiguration,
+) -> Result<ProviderMetadata, ProcessorError> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        provider.issuer.trim_end_matches('/')
+    );
+
+    let metadata = HTTP_CLIENT
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ProviderMetadata>()
+        .await?;
+
+    Ok(metadata)
+}
+
+fn decode(value: Option<&String>) -> Result<Vec<u8>, AuthenticationError> {
+    value
+        .and_then(|value| Base64UrlSafeNoPadding::decode_to_vec(value, None).ok())
+        .ok_or(AuthenticationError::IdentityProvider)
+}
+
+/// Verifies the ID token with the provider's published keys. RS256 and ES256 cover the
+/// providers in use.
+async fn verify_id_token(
+    metadata: &ProviderMetadata,
+    provider: &OidcProviderConfiguration,
+    id_token: &str,
+    nonce: &str,
+) -> Result<JWTClaims<IdTokenClaims>, ProcessorError> {
+    let token_metadata =
+        Token::decode_metadata(id_token).map_err(|_| AuthenticationError::IdentityProvider)?;
+
+    let key_set = HTTP_CLIENT
+        .get(&metadata.jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ProviderJsonWebKeySet>()
+        .await?;
+
+    let key = key_set
+        .keys
+        .iter()
+        .find(|key| match token_metadata.key_id() {
+            Some(key_id) => key.kid.as_deref() == Some(key_id),
+            None => true,
+        })
+        .ok_or(AuthenticationError::IdentityProvider)?;
+
+    let options = VerificationOptions {
+        required_nonce: Some(nonce.to_string()),
+        allowed_issuers: Some(HashSet::from([metadata.issuer.clone()])),
+        allowed_audiences: Some(HashSet::from([provider.client_id.clone()])),
+        ..VerificationOptions::default()
+    };
+
+    let claims = match (token_metadata.algorithm(), key.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let modulus = decode(key.n.as_ref())?;
+            let exponent = decode(key.e.as_ref())?;
+
+            RS256PublicKey::from_components(&modulus, &exponent)
+                .and_then(|key| key.verify_token::<IdTokenClaims>(id_token, Some(options)))
+        }
+        ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+            // Uncompressed SEC1 encoding of the point.
+            let mut point = vec![0x04];
+
+            point.extend(decode(key.x.as_ref())?);
+            point.extend(decode(key.y.as_ref())?);
+
+            ES256PublicKey::from_bytes(&point)
+                .and_then(|key| key.verify_token::<IdTokenClaims>(id_token, Some(options)))
+        }
+        _ => return Err(AuthenticationError::IdentityProvider.into()),
+    };
+
+    claims.map_err(|error| {
+        tracing::warn!("Rejected ID token of {}: {}", provider.name, error);
+
+        AuthenticationError::IdentityProvider.into()
+    })
+}
+
+/// Starts the authorization code flow, returning the URL to send the user to.
+pub async fn begin_authorization(
+    pool: &DatabaseConnectionPool,
+    provider: &OidcProviderConfiguration,
+) -> Result<String, ProcessorError> {
+    let metadata = discover(provider).await?;
+
+    let state = tokens::generate_opaque_token()?;
+    let nonce = tokens::generate_opaque_token()?;
+    let code_verifier = tokens::generate_opaque_token()?;
+    let code_challenge = Base64UrlSafeNoPadding::encode_to_string(hmac_sha256::Hash::hash(
+        code_verifier.as_bytes(),
+    ))?;
+
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(AUTHORIZATION_LIFETIME_MINUTES);
+
+    query!(
+        "
+        DELETE
+        FROM oidc_authorizations
+        WHERE expires_at < ?;
+        ",
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    query!(
+        "
+        INSERT INTO oidc_authorizations (state, provider, code_verifier, nonce, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        ",
+        state,
+        provider.name,
+        code_verifier,
+        nonce,
+        expires_at,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    let parameters = [
+        ("response_type", "code"),
+        ("client_id", provider.client_id.as_str()),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("scope", provider.scopes.as_str()),
+        ("state", state.as_str()),
+        ("nonce", nonce.as_str()),
+        ("code_challenge", code_challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ]
+    .iter()
+    .map(|(name, value)| format!("{}={}", name, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+    .collect::<Vec<String>>()
+    .join("&");
+
+    let separator = if metadata.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    Ok(format!(
+        "{}{}{}",
+        metadata.authorization_endpoint, separator, parameters
+    ))
+}
+
+/// Exchanges the authorization code that the provider redirected back with for the identity of
+/// the user.
+pub async fn complete_authorization(
+    pool: &DatabaseConnectionPool,
+    provider: &OidcProviderConfiguration,
+    code: &str,
+    state: &str,
+) -> Result<ExternalIdentity, ProcessorError> {
+    let now = Utc::now();
+
+    let authorization = query!(
+        r#"
+        SELECT code_verifier as "code_verifier!",
+            nonce as "nonce!",
+            expires_at as "expires_at!: DateTime<Utc>"
+        FROM oidc_authorizations
+        WHERE state = ?1 AND provider = ?2;
+        "#,
+        state,
+        provider.name,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AuthenticationError::InvalidAuthorizationState)?;
+
+    // Every state can be used once, whether the exchange succeeds or not.
+    let redemption = query!(
+        "
+        DELETE
+        FROM oidc_authorizations
+        WHERE state = ?;
+        ",
+        state,
+    )
+    .execute(pool)
+    .await?;
+
+    if redemption.rows_affected() == 0 || authorization.expires_at <= now {
+        return Err(AuthenticationError::InvalidAuthorizationState.into());
+    }
+
+    let metadata = discover(provider).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", authorization.code_verifier.as_str()),
+    ];
+
+    if let Some(client_secret) = &provider.client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    }
+
+    let response = HTTP_CLIENT
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await?;
+
+    let claims = verify_id_token(
+        &metadata,
+        provider,
+        &response.id_token,
+        &authorization.nonce,
+    )
+    .await?;
+
+    Ok(ExternalIdentity {
+        subject: claims
+            .subject
+            .ok_or(AuthenticationError::IdentityProvider)?,
+        email: claims.custom.email.map(|email| email.to_lowercase()),
+        email_verified: claims.custom.email_verified.unwrap_or(false),
+        preferred_username: claims.custom.preferred_username,
+    })
+}
+
+/// Finds the user that the external identity belongs to, creating one on first sign-in.
+///
+/// Identities are linked to an existing account only when both sides have verified the same
+/// email address, so that an account cannot be taken over through a provider that lets users
+/// claim arbitrary addresses.
+pub async fn resolve_user(
+    pool: &DatabaseConnectionPool,
+    provider: &OidcProviderConfiguration,
+    identity: &ExternalIdentity,
+    configuration: &PasswordConfiguration,
+) -> Result<String, ProcessorError> {
+    let now = Utc::now();
+
+    let linked = query!(
+        r#"
+        SELECT user_id as "user_id!"
+        FROM identities
+        WHERE provider = ?1 AND subject = ?2;
+        "#,
+        provider.name,
+        identity.subject,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(linked) = linked {
+        query!(
+            "
+            UPDATE identities
+            SET email        = ?1,
+                last_used_at = ?2
+            WHERE provider = ?3 AND subject = ?4;
+            ",
+            identity.email,
+            now,
+            provider.name,
+            identity.subject,
+        )
+        .execute(pool)
+        .await?;
+
+        return Ok(linked.user_id);
+    }
+
+    let verified_email = identity.email.as_ref().filter(|_| identity.email_verified);
+
+    let existing = match verified_email {
+        Some(email) => query!(
+            r#"
+            SELECT id as "id!"
+            FROM users
+            WHERE email = ? AND email_verified_at IS NOT NULL;
+            "#,
+            email
+        )
+        .fetch_optional(pool)
+        .await?
+        .map(|record| record.id),
+        None => None,
+    };
+
+    let user_id = match existing {
+        Some(user_id) => user_id,
+        None => create_user(pool, identity, configuration).await?,
+    };
+
+    let id = Uuid::new_v4().to_string();
+
+    query!(
+        "
+        INSERT INTO identities (id, user_id, provider, subject, email, last_used_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6);
+        ",
+        id,
+        user_id,
+        provider.name,
+        identity.subject,
+        identity.email,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Creates an account for a new external identity. Its password is random, so signing in with
+/// a password takes a password reset first.
+async fn create_user(
+    pool: &DatabaseConnectionPool,
+    identity: &ExternalIdentity,
+    configuration: &PasswordConfiguration,
+) -> Result<String, ProcessorError> {
+    let now = Utc::now();
+    let created_at = now.to_string();
+    let id = Uuid::new_v4().to_string();
+    let password_hash = password::hash_password(&tokens::generate_opaque_token()?, configuration)?;
+
+    let base_username = identity
+        .preferred_username
+        .clone()
+        .or_else(|| {
+            identity
+                .email
+                .as_ref()
+                .and_then(|email| email.split('@').next().map(str::to_string))
+        })
+        .filter(|username| !username.trim().is_empty())
+        .unwrap_or_else(|| "user".to_string());
+
+    let mut username = base_username.clone();
+
+    while query!("SELECT id FROM users WHERE username = ?;", username)
+        .fetch_optional(pool)
+        .await?
+        .is_some()
+    {
+        username = format!(
+            "{}-{:06x}",
+            base_username,
+            rand::thread_rng().gen_range(0..0x1000000u32)
+        );
+    }
+
+    // The address is left out if another account already uses it.
+    let email = match &identity.email {
+        Some(email) => query!("SELECT id FROM users WHERE email = ?;", email)
+            .fetch_optional(pool)
+            .await?
+            .map_or(Some(email), |_| None),
+        None => None,
+    };
+    let email_verified_at = email.filter(|_| identity.email_verified).map(|_| now);
+
+    query!(
+        "
+        INSERT INTO users (id, username, password, email, email_verified_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        ",
+        id,
+        username,
+        password_hash,
+        email,
+        email_verified_at,
+        created_at,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MockAuthorization, MockIdentityProvider};
+
+    /// Starts an authorization, returning its state and what the identity provider was sent.
+    async fn begin(
+        pool: &DatabaseConnectionPool,
+        identity_provider: &MockIdentityProvider,
+    ) -> (String, MockAuthorization) {
+        let url = begin_authorization(pool, &identity_provider.provider())
+            .await
+            .unwrap();
+        let parameters = testing::query_parameters(&url);
+
+        assert_eq!(parameters["code_challenge_method"], "S256");
+
+        (
+            parameters["state"].clone(),
+            MockAuthorization {
+                subject: "mock-subject".to_string(),
+                email: Some("Alice@Example.com".to_string()),
+                nonce: parameters["nonce"].clone(),
+                code_challenge: parameters["code_challenge"].clone(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn completes_the_authorization_code_flow() {
+        let pool = testing::database().await;
+        let identity_provider = MockIdentityProvider::start();
+        let provider = identity_provider.provider();
+        let (state, authorization) = begin(&pool, &identity_provider).await;
+        let code = identity_provider.issue_code(authorization);
+
+        let identity = complete_authorization(&pool, &provider, &code, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(identity.subject, "mock-subject");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn states_are_used_once() {
+        let pool = testing::database().await;
+        let identity_provider = MockIdentityProvider::start();
+        let provider = identity_provider.provider();
+        let (state, authorization) = begin(&pool, &identity_provider).await;
+        let code = identity_provider.issue_code(authorization.clone());
+
+        complete_authorization(&pool, &provider, &code, &state)
+            .await
+            .unwrap();
+
+        let code = identity_provider.issue_code(authorization);
+        let result = complete_authorization(&pool, &provider, &code, &state).await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidAuthorizationState
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_and_expired_states_are_rejected() {
+        let pool = testing::database().await;
+        let identity_provider = MockIdentityProvider::start();
+        let provider = identity_provider.provider();
+        let (state, authorization) = begin(&pool, &identity_provider).await;
+        let code = identity_provider.issue_code(authorization);
+
+        let result = complete_authorization(&pool, &provider, &code, "unknown").await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidAuthorizationState
+            ))
+        ));
+
+        sqlx::query("UPDATE oidc_authorizations SET expires_at = ? WHERE state = ?;")
+            .bind(Utc::now() - Duration::minutes(1))
+            .bind(&state)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let result = complete_authorization(&pool, &provider, &code, &state).await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidAuthorizationState
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn code_verifiers_must_match_the_challenge() {
+        let pool = testing::database().await;
+        let identity_provider = MockIdentityProvider::start();
+        let provider = identity_provider.provider();
+        let (state, authorization) = begin(&pool, &identity_provider).await;
+        let code = identity_provider.issue_code(MockAuthorization {
+            code_challenge: Base64UrlSafeNoPadding::encode_to_string(hmac_sha256::Hash::hash(
+                b"another verifier",
+            ))
+            .unwrap(),
+            ..authorization
+        });
+
+        let result = complete_authorization(&pool, &provider, &code, &state).await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::IdentityProvider
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn id_tokens_must_carry_the_nonce() {
+        let pool = testing::database().await;
+        let identity_provider = MockIdentityProvider::start();
+        let provider = identity_provider.provider();
+        let (state, authorization) = begin(&pool, &identity_provider).await;
+        let code = identity_provider.issue_code(MockAuthorization {
+            nonce: "another nonce".to_string(),
+            ..authorization
+        });
+
+        let result = complete_authorization(&pool, &provider, &code, &state).await;
+
+        assert!(matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::IdentityProvider
+            ))
+        ));
+    }
+}
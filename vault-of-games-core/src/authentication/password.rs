@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+
+use argon2::{Variant, Version};
+use once_cell::sync::{Lazy, OnceCell};
+use rand::Rng;
+
+use crate::{
+    configuration::PasswordConfiguration, error::ProcessorError, validation::ValidationErrors,
+};
+
+/// Passwords that are among the first to be tried by anyone guessing, one per line.
+static COMMON_PASSWORDS: Lazy<HashSet<&'static str>> =
+    Lazy::new(|| include_str!("common_passwords.txt").lines().collect());
+
+/// Checks a new password against the password policy, recording every violation.
+pub fn validate_password(
+    password: &str,
+    username: &str,
+    configuration: &PasswordConfiguration,
+    errors: &mut ValidationErrors,
+) {
+    if password.chars().count() < configuration.min_length {
+        errors.add(
+            "password",
+            format!(
+                "must be at least {} characters long",
+                configuration.min_length
+            ),
+        );
+    }
+
+    if COMMON_PASSWORDS.contains(password.to_lowercase().as_str()) {
+        errors.add("password", "is too common");
+    }
+
+    if !username.is_empty() && password.eq_ignore_ascii_case(username) {
+        errors.add("password", "must not be the same as the username");
+    }
+}
+
+fn argon2_config(configuration: &PasswordConfiguration) -> argon2::Config<'static> {
+    argon2::Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: configuration.memory_cost,
+        time_cost: configuration.time_cost,
+        lanes: configuration.lanes,
+        ..argon2::Config::default()
+    }
+}
+
+pub fn hash_password(
+    password: &str,
+    configuration: &PasswordConfiguration,
+) -> Result<String, ProcessorError> {
+    Ok(argon2::hash_encoded(
+        password.as_bytes(),
+        &rand::thread_rng().gen::<[u8; 32]>(),
+        &argon2_config(configuration),
+    )?)
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> Result<bool, ProcessorError> {
+    Ok(argon2::verify_encoded(password_hash, password.as_bytes())?)
+}
+
+static DUMMY_PASSWORD_HASH: OnceCell<String> = OnceCell::new();
+
+/// Checks the password against a hash that no account has, so that signing in with an unknown
+/// username takes as long as signing in with a wrong password.
+pub fn verify_dummy_password(
+    password: &str,
+    configuration: &PasswordConfiguration,
+) -> Result<(), ProcessorError> {
+    let password_hash = DUMMY_PASSWORD_HASH.get_or_try_init(|| {
+        hash_password(&rand::thread_rng().gen::<u64>().to_string(), configuration)
+    })?;
+
+    verify_password(password_hash, password)?;
+
+    Ok(())
+}
+
+/// Whether the hash was computed with other parameters than the configured ones, such as those
+/// of `argon2::Config::default()` which passwords used to be hashed with.
+pub fn needs_rehash(password_hash: &str, configuration: &PasswordConfiguration) -> bool {
+    // Encoded hashes look like `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+    let expected = [
+        Variant::Argon2id.as_lowercase_str().to_string(),
+        format!("v={}", Version::Version13.as_u32()),
+        format!(
+            "m={},t={},p={}",
+            configuration.memory_cost, configuration.time_cost, configuration.lanes
+        ),
+    ];
+
+    !password_hash
+        .split('$')
+        .skip(1)
+        .take(3)
+        .eq(expected.iter().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing;
+
+    fn violations(password: &str, username: &str) -> Value {
+        let mut errors = ValidationErrors::new();
+
+        validate_password(
+            password,
+            username,
+            &testing::configuration().password,
+            &mut errors,
+        );
+
+        serde_json::to_value(errors).unwrap()
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        assert_eq!(
+            violations("horse", "alice"),
+            json!({ "password": ["must be at least 10 characters long"] })
+        );
+    }
+
+    #[test]
+    fn common_passwords_are_rejected() {
+        assert_eq!(
+            violations("QwertyUiop", "alice"),
+            json!({ "password": ["is too common"] })
+        );
+    }
+
+    #[test]
+    fn passwords_must_differ_from_the_username() {
+        assert_eq!(
+            violations("Alice-In-Chains", "alice-in-chains"),
+            json!({ "password": ["must not be the same as the username"] })
+        );
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        assert_eq!(
+            violations("password", "password"),
+            json!({
+                "password": [
+                    "must be at least 10 characters long",
+                    "is too common",
+                    "must not be the same as the username",
+                ]
+            })
+        );
+        assert_eq!(violations(testing::PASSWORD, "alice"), json!({}));
+    }
+
+    #[test]
+    fn hashes_with_other_parameters_need_a_rehash() {
+        let configuration = testing::configuration().password;
+        let current = hash_password(testing::PASSWORD, &configuration).unwrap();
+        let baseline = argon2::hash_encoded(
+            testing::PASSWORD.as_bytes(),
+            b"somesaltsomesalt",
+            &argon2::Config::default(),
+        )
+        .unwrap();
+
+        assert!(!needs_rehash(&current, &configuration));
+        assert!(needs_rehash(&baseline, &configuration));
+        assert!(verify_password(&baseline, testing::PASSWORD).unwrap());
+    }
+}
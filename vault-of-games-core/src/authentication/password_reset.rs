@@ -0,0 +1,157 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{query, Sqlite, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Configuration,
+    database::DatabaseConnectionPool,
+    error::ProcessorError,
+    mail::{Mail, Mailer},
+};
+
+use super::{error::AuthenticationError, tokens};
+
+pub const PASSWORD_RESET_TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+/// Issues a single-use password reset token, invalidating the ones issued before it.
+pub async fn issue_reset_token(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+) -> Result<String, ProcessorError> {
+    let token = tokens::generate_opaque_token()?;
+    let token_hash = tokens::hash_opaque_token(&token)?;
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let expires_at = now + Duration::minutes(PASSWORD_RESET_TOKEN_LIFETIME_MINUTES);
+
+    query!(
+        "
+        DELETE
+        FROM password_reset_tokens
+        WHERE user_id = ?1 OR expires_at < ?2;
+        ",
+        user_id,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    query!(
+        "
+        INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5);
+        ",
+        id,
+        user_id,
+        token_hash,
+        expires_at,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+pub async fn send_reset_mail(
+    pool: &DatabaseConnectionPool,
+    mailer: &dyn Mailer,
+    configuration: &Configuration,
+    user_id: &str,
+    email: &str,
+) -> Result<(), ProcessorError> {
+    let token = issue_reset_token(pool, user_id).await?;
+
+    let mail = Mail::new(
+        email.to_string(),
+        "Reset your Vault of Games password".to_string(),
+        format!(
+            "Follow this link within {} minutes to choose a new password:\n\n{}/password/reset?token={}\n\nIf you did not ask for a password reset, you can ignore this message.",
+            PASSWORD_RESET_TOKEN_LIFETIME_MINUTES,
+            configuration.mail.public_url.trim_end_matches('/'),
+            token
+        ),
+    );
+
+    if let Err(error) = mailer.send(mail).await {
+        tracing::error!("Failed to send password reset mail: {}", error);
+    }
+
+    Ok(())
+}
+
+/// Looks up the username of the user that the reset token was issued to, without using it up.
+///
+/// Used and expired tokens are rejected here already, so that they cannot be used to learn the
+/// username through the password policy.
+pub async fn reset_token_owner(
+    pool: &DatabaseConnectionPool,
+    token: &str,
+) -> Result<String, ProcessorError> {
+    let token_hash = tokens::hash_opaque_token(token)?;
+    let now = Utc::now();
+
+    let record = query!(
+        r#"
+        SELECT u.username as "username!"
+        FROM password_reset_tokens prt
+            INNER JOIN users u ON u.id = prt.user_id
+        WHERE prt.token_hash = ?1 AND prt.used_at IS NULL AND prt.expires_at > ?2;
+        "#,
+        token_hash,
+        now,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AuthenticationError::InvalidResetToken)?;
+
+    Ok(record.username)
+}
+
+/// Marks the reset token as used and returns the ID of the user it was issued to. Runs as part of
+/// the password change, so that the token is only used up along with it.
+pub async fn redeem_reset_token(
+    transaction: &mut Transaction<'_, Sqlite>,
+    token: &str,
+) -> Result<String, ProcessorError> {
+    let token_hash = tokens::hash_opaque_token(token)?;
+    let now = Utc::now();
+
+    let record = query!(
+        r#"
+        SELECT id as "id!",
+            user_id as "user_id!",
+            expires_at as "expires_at!: DateTime<Utc>",
+            used_at as "used_at?: DateTime<Utc>"
+        FROM password_reset_tokens
+        WHERE token_hash = ?;
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(AuthenticationError::InvalidResetToken)?;
+
+    if record.used_at.is_some() || record.expires_at <= now {
+        return Err(AuthenticationError::InvalidResetToken.into());
+    }
+
+    // Guards against the same token being redeemed by two concurrent requests.
+    let redemption = query!(
+        "
+        UPDATE password_reset_tokens
+        SET used_at = ?1
+        WHERE id = ?2 AND used_at IS NULL;
+        ",
+        now,
+        record.id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    if redemption.rows_affected() == 0 {
+        return Err(AuthenticationError::InvalidResetToken.into());
+    }
+
+    Ok(record.user_id)
+}
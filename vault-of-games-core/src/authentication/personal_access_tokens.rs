@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use jwt_simple::prelude::{Claims, Duration};
+use sqlx::query;
+
+use crate::{database::DatabaseConnectionPool, error::ProcessorError};
+
+use super::{
+    claims::{AccessTokenClaims, AuthorizationClaims},
+    role::Role,
+    scope::{Scope, Scopes},
+    tokens::{self, ACCESS_TOKEN_LIFETIME_MINUTES},
+};
+
+/// Lets the authorization layer tell personal access tokens apart from JWTs.
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "vog_pat_";
+
+pub fn generate_personal_access_token() -> Result<String, ProcessorError> {
+    Ok(format!(
+        "{}{}",
+        PERSONAL_ACCESS_TOKEN_PREFIX,
+        tokens::generate_opaque_token()?
+    ))
+}
+
+/// Resolves a personal access token into the same claims that a signed access token carries,
+/// provided that it is still valid and grants the required scope.
+pub async fn authorize(
+    pool: &DatabaseConnectionPool,
+    token: &str,
+    scope: Scope,
+) -> Result<Option<AuthorizationClaims>, ProcessorError> {
+    let token_hash = tokens::hash_opaque_token(token)?;
+    let now = Utc::now();
+
+    let record = query!(
+        r#"
+        SELECT pat.id as "id!",
+            pat.user_id as "user_id!",
+            pat.scopes as "scopes!: Scopes",
+            pat.expires_at as "expires_at?: DateTime<Utc>",
+            u.token_generation as "token_generation!: i64",
+            u.role as "role!: Role",
+            u.disabled_at as "disabled_at?: String"
+        FROM personal_access_tokens pat
+                JOIN users u on u.id = pat.user_id
+        WHERE pat.token_hash = ? AND pat.revoked_at IS NULL;
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let record = match record {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let expired = matches!(record.expires_at, Some(expires_at) if expires_at <= now);
+
+    if expired || record.disabled_at.is_some() || !record.scopes.contains(scope) {
+        return Ok(None);
+    }
+
+    query!(
+        "
+        UPDATE personal_access_tokens
+        SET last_used_at = ?1
+        WHERE id = ?2;
+        ",
+        now,
+        record.id,
+    )
+    .execute(pool)
+    .await?;
+
+    let claims = Claims::with_custom_claims(
+        AccessTokenClaims::new(record.id, record.token_generation, record.role)
+            .with_scopes(record.scopes),
+        Duration::from_mins(ACCESS_TOKEN_LIFETIME_MINUTES),
+    )
+    .with_subject(record.user_id);
+
+    Ok(Some(claims))
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use sqlx::{query, Sqlite, Transaction};
+
+use crate::{database::DatabaseConnectionPool, error::ProcessorError};
+
+use super::claims::AuthorizationClaims;
+
+/// How long a revocation verdict is trusted before the database is consulted again.
+const CACHE_TIME_TO_LIVE: Duration = Duration::from_secs(30);
+const CACHE_CAPACITY: usize = 10_000;
+
+struct CachedVerdict {
+    user_id: String,
+    revoked: bool,
+    checked_at: Instant,
+}
+
+/// Keeps recent revocation verdicts per token ID, so that authorized requests do not cost a
+/// database round trip each.
+#[derive(Default)]
+pub struct RevocationCache {
+    verdicts: Mutex<HashMap<String, CachedVerdict>>,
+}
+
+impl RevocationCache {
+    fn get(&self, jwt_id: &str) -> Option<bool> {
+        let verdicts = self.verdicts.lock().unwrap();
+
+        verdicts
+            .get(jwt_id)
+            .filter(|verdict| verdict.checked_at.elapsed() < CACHE_TIME_TO_LIVE)
+            .map(|verdict| verdict.revoked)
+    }
+
+    fn insert(&self, jwt_id: String, user_id: String, revoked: bool) {
+        let mut verdicts = self.verdicts.lock().unwrap();
+
+        if verdicts.len() >= CACHE_CAPACITY {
+            verdicts.retain(|_, verdict| verdict.checked_at.elapsed() < CACHE_TIME_TO_LIVE);
+        }
+
+        verdicts.insert(
+            jwt_id,
+            CachedVerdict {
+                user_id,
+                revoked,
+                checked_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn forget_user(&self, user_id: &str) {
+        self.verdicts
+            .lock()
+            .unwrap()
+            .retain(|_, verdict| verdict.user_id != user_id);
+    }
+}
+
+pub static REVOCATION_CACHE: Lazy<RevocationCache> = Lazy::new(RevocationCache::default);
+
+fn expiration_of(claims: &AuthorizationClaims) -> DateTime<Utc> {
+    claims
+        .expires_at
+        .and_then(|expires_at| DateTime::from_timestamp(expires_at.as_secs() as i64, 0))
+        .unwrap_or_else(Utc::now)
+}
+
+/// Checks whether a token has been revoked, either on its own, with its session or by a sign-out
+/// everywhere.
+pub async fn is_revoked(
+    pool: &DatabaseConnectionPool,
+    claims: &AuthorizationClaims,
+) -> Result<bool, sqlx::Error> {
+    let (jwt_id, user_id) = match (&claims.jwt_id, &claims.subject) {
+        (Some(jwt_id), Some(user_id)) => (jwt_id, user_id),
+        _ => return Ok(true),
+    };
+
+    if let Some(revoked) = REVOCATION_CACHE.get(jwt_id) {
+        return Ok(revoked);
+    }
+
+    let record = query!(
+        r#"
+        SELECT u.token_generation as "token_generation!: i64",
+            EXISTS(SELECT 1 FROM revoked_tokens rt WHERE rt.jwt_id = ?1)
+                OR EXISTS(SELECT 1 FROM sessions s WHERE s.id = ?3 AND s.revoked_at IS NOT NULL)
+                as "revoked!: bool"
+        FROM users u
+        WHERE u.id = ?2;
+        "#,
+        jwt_id,
+        user_id,
+        claims.custom.session_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let revoked = match record {
+        Some(record) => record.revoked || record.token_generation != claims.custom.token_generation,
+        None => true,
+    };
+
+    REVOCATION_CACHE.insert(jwt_id.clone(), user_id.clone(), revoked);
+
+    Ok(revoked)
+}
+
+/// Revokes a single access token until it expires on its own.
+pub async fn revoke_token(
+    pool: &DatabaseConnectionPool,
+    claims: &AuthorizationClaims,
+) -> Result<(), ProcessorError> {
+    let (jwt_id, user_id) = match (&claims.jwt_id, &claims.subject) {
+        (Some(jwt_id), Some(user_id)) => (jwt_id, user_id),
+        _ => return Ok(()),
+    };
+
+    let now = Utc::now();
+    let expires_at = expiration_of(claims);
+
+    query!(
+        "
+        DELETE
+        FROM revoked_tokens
+        WHERE expires_at < ?;
+        ",
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    query!(
+        "
+        INSERT OR IGNORE INTO revoked_tokens (jwt_id, user_id, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4);
+        ",
+        jwt_id,
+        user_id,
+        expires_at,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    REVOCATION_CACHE.insert(jwt_id.clone(), user_id.clone(), true);
+
+    Ok(())
+}
+
+/// Invalidates every access and refresh token issued to the user so far, personal access tokens
+/// included.
+pub async fn revoke_all_tokens(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+) -> Result<(), ProcessorError> {
+    let mut transaction = pool.begin().await?;
+
+    revoke_all_tokens_within(&mut transaction, user_id).await?;
+
+    transaction.commit().await?;
+
+    REVOCATION_CACHE.forget_user(user_id);
+
+    Ok(())
+}
+
+/// Does the writes of [`revoke_all_tokens`] as part of a larger transaction. The caller has to
+/// forget the cached verdicts of the user with [`RevocationCache::forget_user`] once it commits.
+pub async fn revoke_all_tokens_within(
+    transaction: &mut Transaction<'_, Sqlite>,
+    user_id: &str,
+) -> Result<(), ProcessorError> {
+    let now = Utc::now();
+
+    query!(
+        "
+        UPDATE users
+        SET token_generation = token_generation + 1
+        WHERE id = ?;
+        ",
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    query!(
+        "
+        UPDATE refresh_tokens
+        SET revoked_at = ?1,
+            updated_at = ?1
+        WHERE user_id = ?2 AND revoked_at IS NULL;
+        ",
+        now,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    query!(
+        "
+        UPDATE sessions
+        SET revoked_at = ?1
+        WHERE user_id = ?2 AND revoked_at IS NULL;
+        ",
+        now,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    // They do not carry the token generation, so they are revoked one by one.
+    query!(
+        "
+        UPDATE personal_access_tokens
+        SET revoked_at = ?1,
+            updated_at = ?1
+        WHERE user_id = ?2 AND revoked_at IS NULL;
+        ",
+        now,
+        user_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use super::*;
+    use crate::{
+        authentication::{keys::key_ring, tokens},
+        testing::TestApp,
+    };
+
+    async fn read_me(app: &TestApp, access_token: &str) -> StatusCode {
+        app.request(Method::GET, "/v1/users/me", Some(access_token), None)
+            .await
+            .status
+    }
+
+    /// Signs in again, returning the access token and its claims.
+    async fn sign_in(app: &TestApp, username: &str) -> (String, AuthorizationClaims) {
+        let response = app.sign_in(username).await;
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+        let claims = key_ring().verify(&access_token).unwrap();
+
+        (access_token, claims)
+    }
+
+    #[tokio::test]
+    async fn signing_out_revokes_the_access_token_at_once() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let (other_access_token, _) = sign_in(&app, "alice").await;
+
+        // Caches the verdict that the token is still good.
+        assert_eq!(read_me(&app, &access_token).await, StatusCode::OK);
+
+        let sign_out = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-out",
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(sign_out.status, StatusCode::NO_CONTENT);
+        assert_eq!(read_me(&app, &access_token).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(read_me(&app, &other_access_token).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn signing_out_everywhere_revokes_every_earlier_token() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let (other_access_token, _) = sign_in(&app, "alice").await;
+
+        assert_eq!(read_me(&app, &access_token).await, StatusCode::OK);
+        assert_eq!(read_me(&app, &other_access_token).await, StatusCode::OK);
+
+        let sign_out = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-out/everywhere",
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(sign_out.status, StatusCode::NO_CONTENT);
+        assert_eq!(read_me(&app, &access_token).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            read_me(&app, &other_access_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Signing in afterwards works as usual.
+        let (new_access_token, _) = sign_in(&app, "alice").await;
+
+        assert_eq!(read_me(&app, &new_access_token).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn revocations_replace_cached_verdicts() {
+        let app = TestApp::new().await;
+        let (user_id, _) = app.user("alice").await;
+        let (_, revoked_on_its_own) = sign_in(&app, "alice").await;
+        let (_, revoked_with_its_session) = sign_in(&app, "alice").await;
+        let (_, revoked_with_all) = sign_in(&app, "alice").await;
+
+        for claims in [
+            &revoked_on_its_own,
+            &revoked_with_its_session,
+            &revoked_with_all,
+        ] {
+            assert!(!is_revoked(&app.pool, claims).await.unwrap());
+        }
+
+        revoke_token(&app.pool, &revoked_on_its_own).await.unwrap();
+
+        assert!(is_revoked(&app.pool, &revoked_on_its_own).await.unwrap());
+        assert!(!is_revoked(&app.pool, &revoked_with_its_session)
+            .await
+            .unwrap());
+
+        tokens::revoke_token_family(
+            &app.pool,
+            &user_id,
+            &revoked_with_its_session.custom.session_id,
+        )
+        .await
+        .unwrap();
+
+        assert!(is_revoked(&app.pool, &revoked_with_its_session)
+            .await
+            .unwrap());
+        assert!(!is_revoked(&app.pool, &revoked_with_all).await.unwrap());
+
+        revoke_all_tokens(&app.pool, &user_id).await.unwrap();
+
+        assert!(is_revoked(&app.pool, &revoked_with_all).await.unwrap());
+    }
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+use sqlx::Type;
+
+/// Roles are ordered by privilege, every role includes the permissions of the ones before it.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
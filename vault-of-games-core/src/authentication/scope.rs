@@ -0,0 +1,71 @@
+use std::str::FromStr;
+
+use hyper::Method;
+use serde::{Deserialize, Serialize};
+use sqlx::{Database, Decode};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Scope {
+    #[serde(rename = "games:read")]
+    GamesRead,
+    #[serde(rename = "games:write")]
+    GamesWrite,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Scopes {
+    pub content: Vec<Scope>,
+}
+
+impl Scopes {
+    pub fn new(content: Vec<Scope>) -> Self {
+        Self { content }
+    }
+
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.content.contains(&scope)
+    }
+}
+
+impl FromStr for Scopes {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for Scopes
+where
+    &'r str: Decode<'r, DB>,
+{
+    fn decode(
+        value: <DB as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let value = <&str as Decode<DB>>::decode(value)?;
+
+        Ok(value.parse()?)
+    }
+}
+
+/// Scopes a route requires from personal access tokens, depending on whether the request only
+/// reads or also modifies data.
+#[derive(Clone, Copy, Debug)]
+pub struct ScopeRequirement {
+    pub read: Scope,
+    pub write: Scope,
+}
+
+impl ScopeRequirement {
+    pub fn new(read: Scope, write: Scope) -> Self {
+        Self { read, write }
+    }
+
+    pub fn for_method(&self, method: &Method) -> Scope {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS => self.read,
+            _ => self.write,
+        }
+    }
+}
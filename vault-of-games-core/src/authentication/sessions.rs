@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequest, RequestParts},
+};
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::{HeaderMap, USER_AGENT},
+    Request,
+};
+use once_cell::sync::Lazy;
+use sqlx::query;
+
+use crate::{database::DatabaseConnectionPool, error::ProcessorError};
+
+use super::tokens;
+
+/// How often the last-seen timestamps collected by [`LastSeenBuffer`] are written out.
+const LAST_SEEN_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Client that a request came from, as recorded with sessions.
+#[derive(Clone, Debug)]
+pub struct ClientMetadata {
+    pub user_agent: Option<String>,
+    pub address: IpAddr,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for ClientMetadata
+where
+    B: Send,
+{
+    type Rejection = <ConnectInfo<SocketAddr> as FromRequest<B>>::Rejection;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(address) = ConnectInfo::<SocketAddr>::from_request(request).await?;
+
+        Ok(Self {
+            user_agent: request.headers().and_then(user_agent),
+            address: address.ip(),
+        })
+    }
+}
+
+impl ClientMetadata {
+    /// Reads the client of a request that is not passed through extractors, such as in
+    /// middleware.
+    pub fn of_request<B>(request: &Request<B>) -> Option<Self> {
+        let ConnectInfo(address) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+
+        Some(Self {
+            user_agent: user_agent(request.headers()),
+            address: address.ip(),
+        })
+    }
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(USER_AGENT)
+        .and_then(|header_value| header_value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Collects the last time each session was seen, so that authorized requests only cost a map
+/// insertion instead of a database write each.
+#[derive(Default)]
+pub struct LastSeenBuffer {
+    sessions: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl LastSeenBuffer {
+    pub fn touch(&self, session_id: &str) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string(), Utc::now());
+    }
+
+    fn take(&self) -> HashMap<String, DateTime<Utc>> {
+        std::mem::take(&mut *self.sessions.lock().unwrap())
+    }
+}
+
+pub static LAST_SEEN: Lazy<LastSeenBuffer> = Lazy::new(LastSeenBuffer::default);
+
+pub async fn flush_last_seen(pool: &DatabaseConnectionPool) -> Result<(), sqlx::Error> {
+    let sessions = LAST_SEEN.take();
+
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    let mut transaction = pool.begin().await?;
+
+    for (session_id, last_seen_at) in sessions {
+        query!(
+            "
+            UPDATE sessions
+            SET last_seen_at = ?1
+            WHERE id = ?2 AND (last_seen_at IS NULL OR last_seen_at < ?1);
+            ",
+            last_seen_at,
+            session_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await
+}
+
+/// Writes out the last-seen timestamps periodically, for as long as the server runs.
+pub async fn flush_last_seen_periodically(pool: DatabaseConnectionPool) {
+    let mut interval = tokio::time::interval(LAST_SEEN_FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = flush_last_seen(&pool).await {
+            tracing::error!("Failed to record session activity: {}", error);
+        }
+    }
+}
+
+pub async fn create_session(
+    pool: &DatabaseConnectionPool,
+    session_id: &str,
+    user_id: &str,
+    client: &ClientMetadata,
+) -> Result<(), ProcessorError> {
+    let now = Utc::now();
+    let ip_address = client.address.to_string();
+
+    query!(
+        "
+        INSERT INTO sessions (id, user_id, user_agent, ip_address, created_at, last_seen_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?5);
+        ",
+        session_id,
+        user_id,
+        client.user_agent,
+        ip_address,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Signs the session out, including the access tokens that were issued to it.
+pub async fn revoke_session(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    session_id: &str,
+) -> Result<(), ProcessorError> {
+    let session = query!(
+        r#"
+        SELECT id as "id!"
+        FROM sessions
+        WHERE id = ?1 AND user_id = ?2 AND revoked_at IS NULL;
+        "#,
+        session_id,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(ProcessorError::DatabaseError(sqlx::Error::RowNotFound))?;
+
+    tokens::revoke_token_family(pool, user_id, &session.id).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use chrono::Duration as ChronoDuration;
+
+    use super::*;
+    use crate::{
+        authentication::{
+            claims::AuthorizationClaims, error::AuthenticationError, keys, revocation,
+        },
+        testing,
+    };
+
+    async fn insert_user(pool: &DatabaseConnectionPool, id: &str) {
+        sqlx::query("INSERT INTO users (id, username, password) VALUES (?1, ?1, '');")
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn client() -> ClientMetadata {
+        ClientMetadata {
+            user_agent: Some("tests".to_string()),
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+
+    /// Signs the user in, returning the claims of the access token and the refresh token.
+    async fn sign_in(
+        pool: &DatabaseConnectionPool,
+        user_id: &str,
+    ) -> (AuthorizationClaims, String) {
+        keys::initialize_test_key_ring();
+
+        let response = serde_json::to_value(
+            tokens::issue_tokens(pool, user_id, &client())
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let claims = keys::key_ring()
+            .verify(response["access_token"].as_str().unwrap())
+            .unwrap();
+
+        (
+            claims,
+            response["refresh_token"].as_str().unwrap().to_string(),
+        )
+    }
+
+    async fn last_seen_at(pool: &DatabaseConnectionPool, session_id: &str) -> DateTime<Utc> {
+        sqlx::query_scalar("SELECT last_seen_at FROM sessions WHERE id = ?;")
+            .bind(session_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn revoking_a_session_signs_out_only_its_tokens() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (revoked_claims, revoked_refresh_token) = sign_in(&pool, "alice").await;
+        let (kept_claims, kept_refresh_token) = sign_in(&pool, "alice").await;
+
+        assert!(!revocation::is_revoked(&pool, &revoked_claims)
+            .await
+            .unwrap());
+
+        revoke_session(&pool, "alice", &revoked_claims.custom.session_id)
+            .await
+            .unwrap();
+
+        assert!(revocation::is_revoked(&pool, &revoked_claims)
+            .await
+            .unwrap());
+        assert!(!revocation::is_revoked(&pool, &kept_claims).await.unwrap());
+        assert!(matches!(
+            tokens::rotate_refresh_token(&pool, &revoked_refresh_token).await,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidRefreshToken
+            ))
+        ));
+        assert!(tokens::rotate_refresh_token(&pool, &kept_refresh_token)
+            .await
+            .is_ok());
+
+        // Revoked sessions are gone as far as the user can tell.
+        assert!(matches!(
+            revoke_session(&pool, "alice", &revoked_claims.custom.session_id).await,
+            Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sessions_of_others_cannot_be_revoked() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        insert_user(&pool, "bob").await;
+        let (claims, _) = sign_in(&pool, "alice").await;
+
+        assert!(matches!(
+            revoke_session(&pool, "bob", &claims.custom.session_id).await,
+            Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound))
+        ));
+        assert!(!revocation::is_revoked(&pool, &claims).await.unwrap());
+    }
+
+    /// [`LAST_SEEN`] is shared by every test, so all of its flushes happen in this one.
+    #[tokio::test]
+    async fn last_seen_is_flushed_and_never_moves_back() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (claims, _) = sign_in(&pool, "alice").await;
+        let session_id = claims.custom.session_id;
+        let an_hour_ago = Utc::now() - ChronoDuration::hours(1);
+
+        sqlx::query("UPDATE sessions SET last_seen_at = ? WHERE id = ?;")
+            .bind(an_hour_ago)
+            .bind(&session_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        LAST_SEEN.touch(&session_id);
+
+        // Nothing is written until the flush.
+        assert_eq!(last_seen_at(&pool, &session_id).await, an_hour_ago);
+
+        flush_last_seen(&pool).await.unwrap();
+
+        let flushed = last_seen_at(&pool, &session_id).await;
+
+        assert!(flushed > an_hour_ago);
+
+        // A flush with nothing buffered changes nothing.
+        flush_last_seen(&pool).await.unwrap();
+
+        assert_eq!(last_seen_at(&pool, &session_id).await, flushed);
+
+        let in_an_hour = Utc::now() + ChronoDuration::hours(1);
+
+        sqlx::query("UPDATE sessions SET last_seen_at = ? WHERE id = ?;")
+            .bind(in_an_hour)
+            .bind(&session_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        LAST_SEEN.touch(&session_id);
+        flush_last_seen(&pool).await.unwrap();
+
+        assert_eq!(last_seen_at(&pool, &session_id).await, in_an_hour);
+    }
+}
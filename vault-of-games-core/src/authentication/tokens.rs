@@ -0,0 +1,391 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use ct_codecs::{Base64UrlSafeNoPadding, Encoder, Hex};
+use jwt_simple::prelude::{Claims, Duration};
+use rand::Rng;
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{database::DatabaseConnectionPool, error::ProcessorError};
+
+use super::{
+    claims::AccessTokenClaims,
+    error::AuthenticationError,
+    keys::key_ring,
+    revocation::REVOCATION_CACHE,
+    role::Role,
+    sessions::{self, ClientMetadata, LAST_SEEN},
+    AuthenticationResponse,
+};
+
+pub const ACCESS_TOKEN_LIFETIME_MINUTES: u64 = 15;
+pub const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 30;
+
+/// Generates a random, URL-safe token which is meaningless to anyone but the server.
+pub fn generate_opaque_token() -> Result<String, ProcessorError> {
+    let bytes = rand::thread_rng().gen::<[u8; 32]>();
+
+    Ok(Base64UrlSafeNoPadding::encode_to_string(bytes)?)
+}
+
+/// Opaque tokens are only ever stored as SHA-256 digests.
+pub fn hash_opaque_token(token: &str) -> Result<String, ProcessorError> {
+    Ok(Hex::encode_to_string(hmac_sha256::Hash::hash(
+        token.as_bytes(),
+    ))?)
+}
+
+/// Signs a short-lived access token bound to the given refresh token family.
+pub async fn sign_access_token(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    family_id: &str,
+) -> Result<String, ProcessorError> {
+    let user = query!(
+        r#"
+        SELECT token_generation as "token_generation!: i64",
+            role as "role!: Role",
+            disabled_at as "disabled_at?: String"
+        FROM users
+        WHERE id = ?;
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if user.disabled_at.is_some() {
+        return Err(AuthenticationError::AccountDisabled.into());
+    }
+
+    let claims = Claims::with_custom_claims(
+        AccessTokenClaims::new(family_id.to_string(), user.token_generation, user.role),
+        Duration::from_mins(ACCESS_TOKEN_LIFETIME_MINUTES),
+    )
+    .with_subject(user_id)
+    .with_jwt_id(Uuid::new_v4());
+
+    Ok(key_ring().sign(claims)?)
+}
+
+async fn insert_refresh_token(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    family_id: &str,
+) -> Result<String, ProcessorError> {
+    let token = generate_opaque_token()?;
+    let token_hash = hash_opaque_token(&token)?;
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now();
+    let expires_at = now + ChronoDuration::days(REFRESH_TOKEN_LIFETIME_DAYS);
+
+    query!(
+        "
+        INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+        ",
+        id,
+        user_id,
+        family_id,
+        token_hash,
+        expires_at,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Signs an access token and starts a new session, whose refresh tokens form a family.
+pub async fn issue_tokens(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    client: &ClientMetadata,
+) -> Result<AuthenticationResponse, ProcessorError> {
+    let family_id = Uuid::new_v4().to_string();
+
+    let access_token = sign_access_token(pool, user_id, &family_id).await?;
+    let refresh_token = insert_refresh_token(pool, user_id, &family_id).await?;
+
+    sessions::create_session(pool, &family_id, user_id, client).await?;
+
+    Ok(AuthenticationResponse::new(
+        access_token,
+        refresh_token,
+        ACCESS_TOKEN_LIFETIME_MINUTES * 60,
+    ))
+}
+
+/// Revokes every refresh token of the family along with its session, so that the access tokens
+/// issued to it stop working as well.
+pub async fn revoke_token_family(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    family_id: &str,
+) -> Result<(), ProcessorError> {
+    let now = Utc::now();
+
+    query!(
+        "
+        UPDATE refresh_tokens
+        SET revoked_at = ?1,
+            updated_at = ?1
+        WHERE family_id = ?2 AND revoked_at IS NULL;
+        ",
+        now,
+        family_id,
+    )
+    .execute(pool)
+    .await?;
+
+    query!(
+        "
+        UPDATE sessions
+        SET revoked_at = ?1
+        WHERE id = ?2 AND revoked_at IS NULL;
+        ",
+        now,
+        family_id,
+    )
+    .execute(pool)
+    .await?;
+
+    REVOCATION_CACHE.forget_user(user_id);
+
+    Ok(())
+}
+
+/// Exchanges a refresh token for a new pair of tokens.
+///
+/// Every refresh token can be used exactly once. Presenting an already rotated token means that
+/// it has leaked, so the whole family it belongs to is revoked.
+pub async fn rotate_refresh_token(
+    pool: &DatabaseConnectionPool,
+    refresh_token: &str,
+) -> Result<AuthenticationResponse, ProcessorError> {
+    let token_hash = hash_opaque_token(refresh_token)?;
+
+    let record = query!(
+        r#"
+        SELECT id as "id!",
+            user_id as "user_id!",
+            family_id as "family_id!",
+            expires_at as "expires_at!: DateTime<Utc>",
+            rotated_at as "rotated_at?: DateTime<Utc>",
+            revoked_at as "revoked_at?: DateTime<Utc>"
+        FROM refresh_tokens
+        WHERE token_hash = ?;
+        "#,
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AuthenticationError::InvalidRefreshToken)?;
+
+    if record.rotated_at.is_some() {
+        tracing::warn!(
+            "Refresh token reuse detected, revoking token family {}",
+            record.family_id
+        );
+
+        revoke_token_family(pool, &record.user_id, &record.family_id).await?;
+
+        return Err(AuthenticationError::InvalidRefreshToken.into());
+    }
+
+    let now = Utc::now();
+
+    if record.revoked_at.is_some() || record.expires_at <= now {
+        return Err(AuthenticationError::InvalidRefreshToken.into());
+    }
+
+    let rotation = query!(
+        "
+        UPDATE refresh_tokens
+        SET rotated_at = ?1,
+            updated_at = ?1
+        WHERE id = ?2 AND rotated_at IS NULL;
+        ",
+        now,
+        record.id,
+    )
+    .execute(pool)
+    .await?;
+
+    // Another request has rotated the same token in the meantime, which is a reuse as well.
+    if rotation.rows_affected() == 0 {
+        revoke_token_family(pool, &record.user_id, &record.family_id).await?;
+
+        return Err(AuthenticationError::InvalidRefreshToken.into());
+    }
+
+    let refresh_token = insert_refresh_token(pool, &record.user_id, &record.family_id).await?;
+    let access_token = sign_access_token(pool, &record.user_id, &record.family_id).await?;
+
+    LAST_SEEN.touch(&record.family_id);
+
+    Ok(AuthenticationResponse::new(
+        access_token,
+        refresh_token,
+        ACCESS_TOKEN_LIFETIME_MINUTES * 60,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::{authentication::claims::AuthorizationClaims, testing};
+
+    async fn insert_user(pool: &DatabaseConnectionPool, id: &str) {
+        sqlx::query("INSERT INTO users (id, username, password) VALUES (?1, ?1, '');")
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn client() -> ClientMetadata {
+        ClientMetadata {
+            user_agent: Some("tests".to_string()),
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+
+    /// Returns the family of the access token and the refresh token of the response.
+    fn family_and_refresh_token(response: AuthenticationResponse) -> (String, String) {
+        let response = serde_json::to_value(response).unwrap();
+        let claims: AuthorizationClaims = key_ring()
+            .verify(response["access_token"].as_str().unwrap())
+            .unwrap();
+
+        (
+            claims.custom.session_id,
+            response["refresh_token"].as_str().unwrap().to_string(),
+        )
+    }
+
+    async fn sign_in(pool: &DatabaseConnectionPool, user_id: &str) -> (String, String) {
+        crate::authentication::keys::initialize_test_key_ring();
+
+        family_and_refresh_token(issue_tokens(pool, user_id, &client()).await.unwrap())
+    }
+
+    async fn is_family_revoked(pool: &DatabaseConnectionPool, family_id: &str) -> bool {
+        let live_tokens: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM refresh_tokens WHERE family_id = ? AND revoked_at IS NULL;",
+        )
+        .bind(family_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let session_revoked_at: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT revoked_at FROM sessions WHERE id = ?;")
+                .bind(family_id)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+
+        live_tokens == 0 && session_revoked_at.is_some()
+    }
+
+    fn is_invalid_refresh_token(result: Result<AuthenticationResponse, ProcessorError>) -> bool {
+        matches!(
+            result,
+            Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidRefreshToken
+            ))
+        )
+    }
+
+    #[tokio::test]
+    async fn rotation_replaces_the_refresh_token() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (family_id, refresh_token) = sign_in(&pool, "alice").await;
+
+        let (rotated_family_id, rotated_refresh_token) =
+            family_and_refresh_token(rotate_refresh_token(&pool, &refresh_token).await.unwrap());
+
+        assert_eq!(rotated_family_id, family_id);
+        assert_ne!(rotated_refresh_token, refresh_token);
+        assert!(rotate_refresh_token(&pool, &rotated_refresh_token)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_token_revokes_its_family() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (family_id, refresh_token) = sign_in(&pool, "alice").await;
+        let (other_family_id, _) = sign_in(&pool, "alice").await;
+        let (_, replacement) =
+            family_and_refresh_token(rotate_refresh_token(&pool, &refresh_token).await.unwrap());
+
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, &refresh_token).await
+        ));
+        assert!(is_family_revoked(&pool, &family_id).await);
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, &replacement).await
+        ));
+
+        // Other sessions of the same user are left alone.
+        assert!(!is_family_revoked(&pool, &other_family_id).await);
+    }
+
+    #[tokio::test]
+    async fn expired_and_revoked_tokens_are_rejected() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (_, expired) = sign_in(&pool, "alice").await;
+        let (revoked_family_id, revoked) = sign_in(&pool, "alice").await;
+
+        sqlx::query("UPDATE refresh_tokens SET expires_at = ? WHERE token_hash = ?;")
+            .bind(Utc::now() - ChronoDuration::minutes(1))
+            .bind(hash_opaque_token(&expired).unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+        revoke_token_family(&pool, "alice", &revoked_family_id)
+            .await
+            .unwrap();
+
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, &expired).await
+        ));
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, &revoked).await
+        ));
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, "unknown").await
+        ));
+    }
+
+    #[tokio::test]
+    async fn losing_a_concurrent_rotation_revokes_the_family() {
+        let pool = testing::database().await;
+        insert_user(&pool, "alice").await;
+        let (family_id, refresh_token) = sign_in(&pool, "alice").await;
+
+        // Stands in for a request which has rotated the token between the read and the update.
+        sqlx::query(
+            "
+            CREATE TRIGGER lose_rotation BEFORE UPDATE OF rotated_at ON refresh_tokens
+            BEGIN
+                SELECT RAISE(IGNORE);
+            END;
+            ",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(is_invalid_refresh_token(
+            rotate_refresh_token(&pool, &refresh_token).await
+        ));
+        assert!(is_family_revoked(&pool, &family_id).await);
+    }
+}
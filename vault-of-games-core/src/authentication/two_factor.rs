@@ -0,0 +1,389 @@
+use chrono::{DateTime, Utc};
+use ct_codecs::{Encoder, Hex};
+use jwt_simple::prelude::{Claims, Duration};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{database::DatabaseConnectionPool, error::ProcessorError};
+
+use super::{error::AuthenticationError, keys::key_ring, tokens};
+
+const ISSUER: &str = "Vault of Games";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub const SECRET_LENGTH: usize = 20;
+pub const DIGITS: u32 = 6;
+pub const PERIOD_SECONDS: u64 = 30;
+/// Number of periods before and after the current one whose codes are still accepted, to make
+/// up for clock drift.
+pub const ALLOWED_DRIFT: u64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+pub const CHALLENGE_LIFETIME_MINUTES: u64 = 5;
+
+/// Encodes bytes as unpadded RFC 4648 base32, which is what authenticator apps expect.
+pub fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+pub fn decode_base32(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for character in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|candidate| *candidate == character.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+pub fn generate_secret() -> String {
+    encode_base32(&rand::thread_rng().gen::<[u8; SECRET_LENGTH]>())
+}
+
+pub fn provisioning_uri(secret: &str, username: &str) -> String {
+    let issuer = utf8_percent_encode(ISSUER, NON_ALPHANUMERIC);
+
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        utf8_percent_encode(username, NON_ALPHANUMERIC),
+        secret,
+        issuer,
+        DIGITS,
+        PERIOD_SECONDS
+    )
+}
+
+/// HOTP value as defined by RFC 4226, which TOTP (RFC 6238) computes over the time step.
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mac = hmac_sha1_compact::HMAC::mac(&counter.to_be_bytes(), secret);
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        mac[offset] & 0x7f,
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Checks a code against the steps around `unix_time` and returns the matching step.
+///
+/// Steps up to and including `last_used_step` are rejected, so that every code works only once.
+pub fn verify_code(
+    secret: &str,
+    code: &str,
+    unix_time: u64,
+    last_used_step: Option<i64>,
+) -> Option<u64> {
+    let secret = decode_base32(secret)?;
+    let code = code.trim().parse::<u32>().ok()?;
+    let current_step = unix_time / PERIOD_SECONDS;
+
+    (current_step.saturating_sub(ALLOWED_DRIFT)..=current_step + ALLOWED_DRIFT)
+        .filter(|step| !matches!(last_used_step, Some(last_used_step) if *step as i64 <= last_used_step))
+        .find(|step| hotp(&secret, *step) == code)
+}
+
+/// Generates single-use recovery codes such as `3f9a1-c07e2`.
+pub fn generate_recovery_codes() -> Result<Vec<String>, ProcessorError> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Hex::encode_to_string(rand::thread_rng().gen::<[u8; 5]>())?;
+
+            Ok(format!("{}-{}", &code[..5], &code[5..]))
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengePurpose {
+    TwoFactor,
+}
+
+/// Claims of the short-lived token that proves a correct password while the second factor is
+/// still outstanding. It lacks the claims of an access token, so it is never accepted as one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChallengeClaims {
+    pub purpose: ChallengePurpose,
+}
+
+pub fn sign_challenge_token(user_id: &str) -> Result<String, ProcessorError> {
+    let claims = Claims::with_custom_claims(
+        ChallengeClaims {
+            purpose: ChallengePurpose::TwoFactor,
+        },
+        Duration::from_mins(CHALLENGE_LIFETIME_MINUTES),
+    )
+    .with_subject(user_id);
+
+    Ok(key_ring().sign(claims)?)
+}
+
+/// Returns the ID of the user that the challenge token was issued to.
+pub fn verify_challenge_token(token: &str) -> Result<String, ProcessorError> {
+    key_ring()
+        .verify::<ChallengeClaims>(token)
+        .ok()
+        .filter(|claims| claims.custom.purpose == ChallengePurpose::TwoFactor)
+        .and_then(|claims| claims.subject)
+        .ok_or_else(|| AuthenticationError::InvalidChallenge.into())
+}
+
+/// Replaces the recovery codes of the user, returning the new ones in plain text for the only
+/// time.
+pub async fn replace_recovery_codes(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+) -> Result<Vec<String>, ProcessorError> {
+    let recovery_codes = generate_recovery_codes()?;
+
+    query!(
+        "
+        DELETE
+        FROM recovery_codes
+        WHERE user_id = ?;
+        ",
+        user_id,
+    )
+    .execute(pool)
+    .await?;
+
+    for recovery_code in recovery_codes.iter() {
+        let id = Uuid::new_v4().to_string();
+        let code_hash = tokens::hash_opaque_token(recovery_code)?;
+
+        query!(
+            "
+            INSERT INTO recovery_codes (id, user_id, code_hash)
+            VALUES (?1, ?2, ?3);
+            ",
+            id,
+            user_id,
+            code_hash,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(recovery_codes)
+}
+
+/// Accepts either a code from the authenticator app or an unused recovery code, and consumes it.
+pub async fn verify_second_factor(
+    pool: &DatabaseConnectionPool,
+    user_id: &str,
+    code: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, ProcessorError> {
+    let user = query!(
+        r#"
+        SELECT totp_secret as "totp_secret?: String",
+            totp_last_used_step as "totp_last_used_step?: i64"
+        FROM users
+        WHERE id = ?;
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let secret = match user.totp_secret {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+
+    if let Some(step) = verify_code(
+        &secret,
+        code,
+        now.timestamp() as u64,
+        user.totp_last_used_step,
+    ) {
+        let step = step as i64;
+
+        // Guards against the same code being redeemed by two concurrent requests.
+        let result = query!(
+            "
+            UPDATE users
+            SET totp_last_used_step = ?1
+            WHERE id = ?2 AND (totp_last_used_step IS NULL OR totp_last_used_step < ?1);
+            ",
+            step,
+            user_id,
+        )
+        .execute(pool)
+        .await?;
+
+        return Ok(result.rows_affected() == 1);
+    }
+
+    let code_hash = tokens::hash_opaque_token(&code.trim().to_lowercase())?;
+
+    let result = query!(
+        "
+        UPDATE recovery_codes
+        SET used_at = ?1
+        WHERE user_id = ?2 AND code_hash = ?3 AND used_at IS NULL;
+        ",
+        now,
+        user_id,
+        code_hash,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secret of the test vectors of RFC 4226 and RFC 6238 for SHA-1.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(RFC_SECRET, counter as u64),
+                *code,
+                "counter {}",
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc_6238() {
+        // The RFC lists eight digits, of which the last six are ours.
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+
+        for (unix_time, code) in vectors {
+            assert_eq!(
+                hotp(RFC_SECRET, unix_time / PERIOD_SECONDS),
+                code % 10u32.pow(DIGITS),
+                "time {}",
+                unix_time
+            );
+        }
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        let secret = encode_base32(RFC_SECRET);
+
+        assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(decode_base32(&secret).as_deref(), Some(RFC_SECRET));
+        assert_eq!(
+            decode_base32(&secret.to_lowercase()).as_deref(),
+            Some(RFC_SECRET)
+        );
+
+        for length in 0..=SECRET_LENGTH {
+            let bytes = (0..length as u8).collect::<Vec<_>>();
+
+            assert_eq!(decode_base32(&encode_base32(&bytes)), Some(bytes));
+        }
+
+        assert_eq!(decode_base32("GEZDGNBV1"), None);
+    }
+
+    #[test]
+    fn codes_are_accepted_within_the_allowed_drift() {
+        let secret = encode_base32(RFC_SECRET);
+        let code_at = |step: u64| format!("{:06}", hotp(RFC_SECRET, step));
+        // The last second of step 100.
+        let unix_time = 101 * PERIOD_SECONDS - 1;
+
+        assert_eq!(
+            verify_code(&secret, &code_at(99), unix_time, None),
+            Some(99)
+        );
+        assert_eq!(
+            verify_code(&secret, &code_at(100), unix_time, None),
+            Some(100)
+        );
+        assert_eq!(
+            verify_code(&secret, &code_at(101), unix_time, None),
+            Some(101)
+        );
+        assert_eq!(verify_code(&secret, &code_at(98), unix_time, None), None);
+        assert_eq!(verify_code(&secret, &code_at(102), unix_time, None), None);
+
+        // One second later step 101 is current, so step 99 falls out of the window.
+        assert_eq!(
+            verify_code(&secret, &code_at(99), unix_time + 1, None),
+            None
+        );
+        assert_eq!(
+            verify_code(&secret, &code_at(102), unix_time + 1, None),
+            Some(102)
+        );
+    }
+
+    #[test]
+    fn used_steps_are_rejected() {
+        let secret = encode_base32(RFC_SECRET);
+        let code = format!("{:06}", hotp(RFC_SECRET, 100));
+        let unix_time = 100 * PERIOD_SECONDS;
+
+        assert_eq!(verify_code(&secret, &code, unix_time, Some(99)), Some(100));
+        assert_eq!(verify_code(&secret, &code, unix_time, Some(100)), None);
+        assert_eq!(verify_code(&secret, &code, unix_time, Some(101)), None);
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let secret = encode_base32(RFC_SECRET);
+
+        assert_eq!(verify_code(&secret, "not a code", 59, None), None);
+        assert_eq!(verify_code("not base32!", "287082", 59, None), None);
+        assert_eq!(verify_code(&secret, " 287082 ", 59, None), Some(1));
+    }
+}
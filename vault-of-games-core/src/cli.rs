@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::authentication::keys::{self, KeyFormat, KeyRing};
+
+const USAGE: &str = "\
+Usage: vault-of-games-core [COMMAND]
+
+Commands:
+    serve                                   Run the server (default)
+    keys generate [--pem]                   Generate a new signing key
+    keys rotate [--pem]                     Generate a new signing key and retire the others
+    keys show-public [--key-id ID] [--pem]  Print a public key as a JWK, or as PEM";
+
+pub enum Command {
+    Serve,
+    Keys(KeysCommand),
+}
+
+pub enum KeysCommand {
+    Generate {
+        format: KeyFormat,
+    },
+    Rotate {
+        format: KeyFormat,
+    },
+    ShowPublic {
+        key_id: Option<String>,
+        format: KeyFormat,
+    },
+}
+
+impl Command {
+    pub fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Self> {
+        let arguments = arguments.into_iter().collect::<Vec<String>>();
+        let arguments = arguments.iter().map(String::as_str).collect::<Vec<&str>>();
+
+        let format = if arguments.contains(&"--pem") {
+            KeyFormat::Pem
+        } else {
+            KeyFormat::Raw
+        };
+
+        let command = match arguments.as_slice() {
+            [] | ["serve"] => Command::Serve,
+            ["keys", "generate", ..] => Command::Keys(KeysCommand::Generate { format }),
+            ["keys", "rotate", ..] => Command::Keys(KeysCommand::Rotate { format }),
+            ["keys", "show-public", options @ ..] => Command::Keys(KeysCommand::ShowPublic {
+                key_id: options
+                    .iter()
+                    .position(|option| *option == "--key-id")
+                    .map(|index| {
+                        options
+                            .get(index + 1)
+                            .map(|key_id| key_id.to_string())
+                            .ok_or_else(|| anyhow!("--key-id requires a value.\n\n{}", USAGE))
+                    })
+                    .transpose()?,
+                format,
+            }),
+            _ => return Err(anyhow!("Unknown command.\n\n{}", USAGE)),
+        };
+
+        Ok(command)
+    }
+}
+
+impl KeysCommand {
+    pub fn execute(self, directory: &Path) -> Result<()> {
+        match self {
+            KeysCommand::Generate { format } => {
+                let key = keys::generate_key(directory, format)?;
+
+                println!("Generated key {} in {}.", key.key_id, directory.display());
+            }
+            KeysCommand::Rotate { format } => {
+                let key = keys::rotate_keys(directory, format)?;
+
+                println!(
+                    "Rotated to key {}, restart the server to start signing with it.",
+                    key.key_id
+                );
+            }
+            KeysCommand::ShowPublic { key_id, format } => {
+                print!(
+                    "{}",
+                    Self::show_public(directory, key_id.as_deref(), format)?
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Formats a public key, the signing key unless `key_id` names another one.
+    fn show_public(directory: &Path, key_id: Option<&str>, format: KeyFormat) -> Result<String> {
+        let key_ring = KeyRing::from_directory(directory, None)?;
+
+        let key = match key_id {
+            Some(key_id) => key_ring
+                .find(key_id)
+                .ok_or_else(|| anyhow!("Unknown key ID {}.", key_id))?,
+            None => key_ring.signing_key(),
+        };
+
+        Ok(match format {
+            KeyFormat::Raw => format!(
+                "{}\n",
+                serde_json::to_string_pretty(&key.to_json_web_key()?)?
+            ),
+            KeyFormat::Pem => key.public_key.to_pem(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ct_codecs::{Base64UrlSafeNoPadding, Encoder};
+    use jwt_simple::prelude::Ed25519PublicKey;
+
+    use super::*;
+    use crate::testing::TemporaryDirectory;
+
+    fn parse(arguments: &[&str]) -> Result<Command> {
+        Command::parse(arguments.iter().map(|argument| argument.to_string()))
+    }
+
+    /// Generates a key into the directory, returning the ID of the key.
+    fn generate(directory: &Path, format: KeyFormat) -> String {
+        KeysCommand::Generate { format }.execute(directory).unwrap();
+
+        fs::read_dir(directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert!(matches!(parse(&[]), Ok(Command::Serve)));
+        assert!(matches!(parse(&["serve"]), Ok(Command::Serve)));
+        assert!(matches!(
+            parse(&["keys", "generate"]),
+            Ok(Command::Keys(KeysCommand::Generate {
+                format: KeyFormat::Raw
+            }))
+        ));
+        assert!(matches!(
+            parse(&["keys", "rotate", "--pem"]),
+            Ok(Command::Keys(KeysCommand::Rotate {
+                format: KeyFormat::Pem
+            }))
+        ));
+
+        match parse(&["keys", "show-public", "--key-id", "20220101000000", "--pem"]) {
+            Ok(Command::Keys(KeysCommand::ShowPublic { key_id, format })) => {
+                assert_eq!(key_id.as_deref(), Some("20220101000000"));
+                assert_eq!(format, KeyFormat::Pem);
+            }
+            _ => panic!("expected show-public"),
+        }
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        for arguments in [
+            &["launch"][..],
+            &["keys"],
+            &["keys", "delete"],
+            &["serve", "--port"],
+        ] {
+            let error = parse(arguments).err().unwrap();
+
+            assert!(error.to_string().starts_with("Unknown command."));
+            assert!(error.to_string().contains(USAGE));
+        }
+
+        let error = parse(&["keys", "show-public", "--key-id"]).err().unwrap();
+
+        assert!(error.to_string().starts_with("--key-id requires a value."));
+    }
+
+    #[test]
+    fn generated_keys_are_shown_as_json_web_keys() {
+        let directory = TemporaryDirectory::new();
+        let key_id = generate(&directory, KeyFormat::Raw);
+        let public_key = fs::read(directory.join(&key_id).join("public.key")).unwrap();
+
+        let shown: serde_json::Value = serde_json::from_str(
+            &KeysCommand::show_public(&directory, None, KeyFormat::Raw).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(shown["kid"], key_id.as_str());
+        assert_eq!(
+            shown["x"],
+            Base64UrlSafeNoPadding::encode_to_string(public_key)
+                .unwrap()
+                .as_str()
+        );
+        assert!(shown.get("d").is_none());
+        assert!(KeysCommand::show_public(&directory, Some("unknown"), KeyFormat::Raw).is_err());
+    }
+
+    #[test]
+    fn generated_keys_are_shown_as_pem() {
+        let directory = TemporaryDirectory::new();
+        let key_id = generate(&directory, KeyFormat::Pem);
+        let public_key = fs::read_to_string(directory.join(&key_id).join("public.key")).unwrap();
+
+        let shown = KeysCommand::show_public(&directory, Some(&key_id), KeyFormat::Pem).unwrap();
+
+        assert!(public_key.starts_with("-----BEGIN PUBLIC KEY-----"));
+        assert_eq!(shown, public_key);
+        assert!(Ed25519PublicKey::from_pem(&shown).is_ok());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let private_key = fs::metadata(directory.join(&key_id).join("private.key")).unwrap();
+
+            assert_eq!(private_key.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn missing_keys_fail_the_startup_with_a_hint() {
+        let directory = TemporaryDirectory::new();
+
+        for directory in [directory.join("missing"), directory.to_path_buf()] {
+            let error = KeyRing::load(&directory, None).err().unwrap();
+
+            assert!(error
+                .to_string()
+                .contains("run `vault-of-games-core keys generate`"));
+        }
+    }
+}
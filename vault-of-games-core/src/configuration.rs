@@ -0,0 +1,289 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+/// Reads an environment variable, falling back to `default` when it is not set.
+fn variable<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|error| anyhow!("Invalid value for {}: {}", name, error)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LockoutConfiguration {
+    /// Failed sign-in attempts allowed per username or client IP before locking it out.
+    pub threshold: u32,
+    /// Length of the first lockout, which doubles with every further failure.
+    pub base_duration_seconds: u64,
+    pub max_duration_seconds: u64,
+    /// Failures older than this are forgotten.
+    pub window_seconds: u64,
+}
+
+impl LockoutConfiguration {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            threshold: variable("SIGN_IN_LOCKOUT_THRESHOLD", 5)?,
+            base_duration_seconds: variable("SIGN_IN_LOCKOUT_BASE_SECONDS", 30)?,
+            max_duration_seconds: variable("SIGN_IN_LOCKOUT_MAX_SECONDS", 3600)?,
+            window_seconds: variable("SIGN_IN_FAILURE_WINDOW_SECONDS", 900)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordConfiguration {
+    pub min_length: usize,
+    /// Memory used by argon2, in KiB.
+    pub memory_cost: u32,
+    /// Number of argon2 passes over the memory.
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl PasswordConfiguration {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            min_length: variable("PASSWORD_MIN_LENGTH", 10)?,
+            memory_cost: variable("ARGON2_MEMORY_COST", 19456)?,
+            time_cost: variable("ARGON2_TIME_COST", 2)?,
+            lanes: variable("ARGON2_LANES", 1)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MailTransport {
+    /// Logs mail instead of sending it, optionally keeping a copy of each in `directory`.
+    Log { directory: Option<PathBuf> },
+    Smtp {
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct MailConfiguration {
+    pub transport: MailTransport,
+    pub sender: String,
+    /// Base URL of the client application, which links in mail point to.
+    pub public_url: String,
+}
+
+impl MailConfiguration {
+    fn from_environment() -> Result<Self> {
+        let transport = match variable("MAIL_TRANSPORT", "log".to_string())?.as_str() {
+            "log" => MailTransport::Log {
+                directory: std::env::var("MAIL_DIRECTORY").ok().map(PathBuf::from),
+            },
+            "smtp" => MailTransport::Smtp {
+                host: std::env::var("SMTP_HOST")
+                    .map_err(|_| anyhow!("SMTP_HOST is required for the smtp mail transport"))?,
+                port: variable("SMTP_PORT", 587)?,
+                username: std::env::var("SMTP_USERNAME").ok(),
+                password: std::env::var("SMTP_PASSWORD").ok(),
+            },
+            transport => return Err(anyhow!("Unknown mail transport: {}", transport)),
+        };
+
+        Ok(Self {
+            transport,
+            sender: variable(
+                "MAIL_SENDER",
+                "Vault of Games <no-reply@localhost>".to_string(),
+            )?,
+            public_url: variable("PUBLIC_URL", "http://localhost:3000".to_string())?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailVerificationConfiguration {
+    pub token_lifetime_minutes: i64,
+    /// Whether accounts have to verify their email address before they can sign in.
+    pub required_for_sign_in: bool,
+    /// Whether password reset links are only mailed to verified email addresses.
+    pub required_for_password_reset: bool,
+}
+
+impl EmailVerificationConfiguration {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            token_lifetime_minutes: variable("EMAIL_VERIFICATION_LIFETIME_MINUTES", 1440)?,
+            required_for_sign_in: variable("EMAIL_VERIFICATION_REQUIRED_FOR_SIGN_IN", false)?,
+            required_for_password_reset: variable(
+                "EMAIL_VERIFICATION_REQUIRED_FOR_PASSWORD_RESET",
+                true,
+            )?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcProviderConfiguration {
+    /// Name that the provider is addressed by in the endpoints, such as `corporate`.
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    /// Left out for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+impl OidcProviderConfiguration {
+    fn from_environment(name: &str) -> Result<Self> {
+        let prefix = format!("OIDC_{}", name.to_uppercase().replace('-', "_"));
+        let required = |suffix: &str| {
+            let variable = format!("{}_{}", prefix, suffix);
+
+            std::env::var(&variable).map_err(|_| {
+                anyhow!(
+                    "{} is required for the {} identity provider",
+                    variable,
+                    name
+                )
+            })
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            issuer: required("ISSUER")?,
+            client_id: required("CLIENT_ID")?,
+            client_secret: std::env::var(format!("{}_CLIENT_SECRET", prefix)).ok(),
+            redirect_uri: required("REDIRECT_URI")?,
+            scopes: variable(
+                &format!("{}_SCOPES", prefix),
+                "openid email profile".to_string(),
+            )?,
+        })
+    }
+}
+
+/// External identity providers that users can sign in with, listed by name in `OIDC_PROVIDERS`
+/// and configured through `OIDC_<NAME>_*` variables each.
+#[derive(Clone, Debug, Default)]
+pub struct OidcConfiguration {
+    pub providers: Vec<OidcProviderConfiguration>,
+}
+
+impl OidcConfiguration {
+    fn from_environment() -> Result<Self> {
+        let providers = std::env::var("OIDC_PROVIDERS").unwrap_or_default();
+
+        Ok(Self {
+            providers: providers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(OidcProviderConfiguration::from_environment)
+                .collect::<Result<Vec<OidcProviderConfiguration>>>()?,
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&OidcProviderConfiguration> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+}
+
+/// Where clients may keep the tokens that authorize their requests.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenTransport {
+    /// Bearer tokens in the `Authorization` header.
+    Header,
+    /// `HttpOnly` cookies, guarded against CSRF with a double-submit token. Signing in does not
+    /// return the tokens in the response body.
+    Cookie,
+    /// Either of the two. Signing in sets the cookies and returns the tokens as well.
+    Both,
+}
+
+impl TokenTransport {
+    pub fn accepts_header(&self) -> bool {
+        matches!(self, TokenTransport::Header | TokenTransport::Both)
+    }
+
+    pub fn accepts_cookie(&self) -> bool {
+        matches!(self, TokenTransport::Cookie | TokenTransport::Both)
+    }
+}
+
+impl FromStr for TokenTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "header" => Ok(TokenTransport::Header),
+            "cookie" => Ok(TokenTransport::Cookie),
+            "both" => Ok(TokenTransport::Both),
+            transport => Err(format!("unknown token transport {}", transport)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CookieConfiguration {
+    pub transport: TokenTransport,
+    /// Only turned off for local development over plain HTTP.
+    pub secure: bool,
+    pub domain: Option<String>,
+}
+
+impl CookieConfiguration {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            transport: variable("TOKEN_TRANSPORT", TokenTransport::Header)?,
+            secure: variable("COOKIE_SECURE", true)?,
+            domain: std::env::var("COOKIE_DOMAIN").ok(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AccountDeletionConfiguration {
+    /// Days between asking for an account to be deleted and its removal, during which signing in
+    /// cancels the deletion. Accounts are removed right away with a grace period of 0.
+    pub grace_period_days: i64,
+}
+
+impl AccountDeletionConfiguration {
+    fn from_environment() -> Result<Self> {
+        Ok(Self {
+            grace_period_days: variable("ACCOUNT_DELETION_GRACE_PERIOD_DAYS", 30)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Configuration {
+    pub lockout: LockoutConfiguration,
+    pub password: PasswordConfiguration,
+    pub mail: MailConfiguration,
+    pub email_verification: EmailVerificationConfiguration,
+    pub oidc: OidcConfiguration,
+    pub account_deletion: AccountDeletionConfiguration,
+    pub cookies: CookieConfiguration,
+}
+
+impl Configuration {
+    pub fn from_environment() -> Result<Self> {
+        Ok(Self {
+            lockout: LockoutConfiguration::from_environment()?,
+            password: PasswordConfiguration::from_environment()?,
+            mail: MailConfiguration::from_environment()?,
+            email_verification: EmailVerificationConfiguration::from_environment()?,
+            oidc: OidcConfiguration::from_environment()?,
+            account_deletion: AccountDeletionConfiguration::from_environment()?,
+            cookies: CookieConfiguration::from_environment()?,
+        })
+    }
+}
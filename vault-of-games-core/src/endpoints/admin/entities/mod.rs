@@ -0,0 +1,27 @@
+pub mod payloads;
+
+use serde::Serialize;
+
+use crate::authentication::role::Role;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ManagedUser {
+    pub id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified_at: Option<String>,
+    pub role: Role,
+    pub disabled_at: Option<String>,
+    pub deletion_scheduled_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ManagedCategory {
+    pub id: String,
+    pub name: String,
+    pub games: i64,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
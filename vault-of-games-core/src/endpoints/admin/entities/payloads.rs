@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::{audit::AuditAction, authentication::role::Role};
+
+#[derive(Deserialize)]
+pub struct UserRoleUpdatePayload {
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct CategoryUpdatePayload {
+    pub name: String,
+}
+
+/// Filters of the audit log, all of which are optional.
+#[derive(Deserialize)]
+pub struct AuditEventFilter {
+    #[serde(default)]
+    pub actor_id: Option<String>,
+    #[serde(default)]
+    pub target_id: Option<String>,
+    #[serde(default)]
+    pub action: Option<AuditAction>,
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,5 @@
+pub mod entities;
+mod processor;
+pub mod router;
+
+pub struct AdminEndpoint;
@@ -0,0 +1,652 @@
+use anyhow::Result;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use sqlx::{query, query_as};
+
+use crate::{
+    audit::{AuditAction, AuditEvent, AuditRecord},
+    authentication::{
+        claims::AuthorizationClaims,
+        error::AuthenticationError,
+        lockout::{self, LockoutKey},
+        revocation,
+        role::Role,
+        sessions::ClientMetadata,
+    },
+    database::DatabaseConnectionPool,
+    error::ProcessorError,
+    pagination::{Page, Pagination},
+};
+
+use super::entities::{
+    payloads::{AuditEventFilter, CategoryUpdatePayload, UserRoleUpdatePayload},
+    ManagedCategory, ManagedUser,
+};
+
+#[derive(Default)]
+pub struct AdminProcessor;
+
+impl AdminProcessor {
+    pub async fn read_users(
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let users = query_as!(
+            ManagedUser,
+            r#"
+            SELECT id as "id!",
+                username as "username!",
+                email as "email?",
+                email_verified_at as "email_verified_at?: String",
+                role as "role!: Role",
+                disabled_at as "disabled_at?: String",
+                deletion_scheduled_at as "deletion_scheduled_at?: String",
+                created_at as "created_at!: String",
+                updated_at as "updated_at?: String"
+            FROM users
+            ORDER BY created_at DESC;
+            "#
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(Json(users))
+    }
+
+    pub async fn update_user_role(
+        Path(id): Path<String>,
+        Json(payload): Json<UserRoleUpdatePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        // Admins cannot demote themselves, so that there is always someone left to manage roles.
+        if claims.subject.as_deref() == Some(id.as_str()) {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::Forbidden,
+            ));
+        }
+
+        let user = query!(
+            "
+            UPDATE users
+            SET role       = ?1,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+            payload.role,
+            id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if user.rows_affected() == 0 {
+            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+
+        // Issued tokens still carry the previous role.
+        revocation::revoke_all_tokens(&pool, &id).await?;
+
+        Self::record_admin_event(&pool, AuditAction::UserRoleChanged, &claims, &id, &client).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    pub async fn disable_user(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if claims.subject.as_deref() == Some(id.as_str()) {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::Forbidden,
+            ));
+        }
+
+        let now = Utc::now().to_string();
+
+        let user = query!(
+            "
+            UPDATE users
+            SET disabled_at = ?1,
+                updated_at  = CURRENT_TIMESTAMP
+            WHERE id = ?2 AND disabled_at IS NULL;
+            ",
+            now,
+            id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if user.rows_affected() == 0 {
+            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+
+        revocation::revoke_all_tokens(&pool, &id).await?;
+
+        Self::record_admin_event(&pool, AuditAction::UserDisabled, &claims, &id, &client).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    pub async fn enable_user(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user = query!(
+            "
+            UPDATE users
+            SET disabled_at = NULL,
+                updated_at  = CURRENT_TIMESTAMP
+            WHERE id = ?1 AND disabled_at IS NOT NULL;
+            ",
+            id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if user.rows_affected() == 0 {
+            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+
+        Self::record_admin_event(&pool, AuditAction::UserEnabled, &claims, &id, &client).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    /// Lifts a sign-in lockout of the user before it expires on its own.
+    pub async fn unlock_user(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user = query!(
+            "
+            SELECT username
+            FROM users
+            WHERE id = ?;
+            ",
+            id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        lockout::clear_failures(&pool, &LockoutKey::Username(&user.username)).await?;
+
+        Self::record_admin_event(&pool, AuditAction::UserUnlocked, &claims, &id, &client).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    /// Searches the audit log across all users, newest first.
+    pub async fn read_audit_events(
+        Query(filter): Query<AuditEventFilter>,
+        Query(pagination): Query<Pagination>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let limit = pagination.limit();
+        let offset = pagination.offset();
+
+        let events = query_as!(
+            AuditEvent,
+            r#"
+            SELECT id as "id!",
+                actor_id as "actor_id?",
+                action as "action!: AuditAction",
+                target_id as "target_id?",
+                ip_address as "ip_address?",
+                user_agent as "user_agent?",
+                created_at as "created_at!: String"
+            FROM audit_events
+            WHERE (?1 IS NULL OR actor_id = ?1)
+              AND (?2 IS NULL OR target_id = ?2)
+              AND (?3 IS NULL OR action = ?3)
+              AND (?4 IS NULL OR created_at >= ?4)
+              AND (?5 IS NULL OR created_at < ?5)
+            ORDER BY created_at DESC
+            LIMIT ?6 OFFSET ?7;
+            "#,
+            filter.actor_id,
+            filter.target_id,
+            filter.action,
+            filter.since,
+            filter.until,
+            limit,
+            offset,
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        let total = query!(
+            r#"
+            SELECT COUNT(*) as "total!: i64"
+            FROM audit_events
+            WHERE (?1 IS NULL OR actor_id = ?1)
+              AND (?2 IS NULL OR target_id = ?2)
+              AND (?3 IS NULL OR action = ?3)
+              AND (?4 IS NULL OR created_at >= ?4)
+              AND (?5 IS NULL OR created_at < ?5);
+            "#,
+            filter.actor_id,
+            filter.target_id,
+            filter.action,
+            filter.since,
+            filter.until,
+        )
+        .fetch_one(&pool)
+        .await?
+        .total;
+
+        Ok(Json(Page::new(events, &pagination, total)))
+    }
+
+    pub async fn read_categories(
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let categories = query_as!(
+            ManagedCategory,
+            r#"
+            SELECT c.id as "id!",
+                c.name as "name!",
+                COUNT(gc.id) as "games!: i64",
+                c.created_at as "created_at!: String",
+                c.updated_at as "updated_at?: String"
+            FROM categories c
+                    LEFT JOIN games_categories gc on c.id = gc.category_id
+            GROUP BY c.id, c.name, c.created_at, c.updated_at
+            ORDER BY c.name;
+            "#
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(Json(categories))
+    }
+
+    pub async fn update_category(
+        Path(id): Path<String>,
+        Json(payload): Json<CategoryUpdatePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let category = query!(
+            "
+            UPDATE categories
+            SET name       = ?1,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+            payload.name,
+            id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if category.rows_affected() == 0 {
+            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+
+        Self::record_admin_event(&pool, AuditAction::CategoryUpdated, &claims, &id, &client).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    pub async fn delete_category(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let category = query!(
+            "
+            DELETE
+            FROM categories
+            WHERE id = ?;
+            ",
+            id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if category.rows_affected() == 0 {
+            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+        }
+
+        Self::record_admin_event(&pool, AuditAction::CategoryDeleted, &claims, &id, &client).await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Records an action that an admin or moderator performed on a user or category.
+    async fn record_admin_event(
+        pool: &DatabaseConnectionPool,
+        action: AuditAction,
+        claims: &AuthorizationClaims,
+        target_id: &str,
+        client: &ClientMetadata,
+    ) {
+        let mut record = AuditRecord::new(action)
+            .with_target(target_id)
+            .with_client(client);
+
+        if let Some(actor_id) = &claims.subject {
+            record = record.with_actor(actor_id);
+        }
+
+        record.record(pool).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    use crate::testing::TestApp;
+
+    async fn audit_events(app: &TestApp, access_token: &str, query: &str) -> Value {
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/v1/admin/audit-events?{}", query),
+                Some(access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        response.body
+    }
+
+    #[tokio::test]
+    async fn audit_events_are_filtered() {
+        let app = TestApp::new().await;
+        let (alice, _) = app.user("alice").await;
+        app.user("bob").await;
+        let (_, admin_token) = app.user_with_role("admin", "admin").await;
+
+        let all = audit_events(&app, &admin_token, "").await;
+
+        assert_eq!(all["total"], 6);
+
+        let by_actor = audit_events(&app, &admin_token, &format!("actor_id={}", alice)).await;
+
+        assert_eq!(by_actor["total"], 2);
+        assert!(by_actor["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|item| item["actor_id"] == alice.as_str()));
+
+        let by_action = audit_events(&app, &admin_token, "action=sign_up").await;
+
+        assert_eq!(by_action["total"], 3);
+        assert!(by_action["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|item| item["action"] == "sign_up"));
+
+        let combined = audit_events(
+            &app,
+            &admin_token,
+            &format!("target_id={}&action=sign_in", alice),
+        )
+        .await;
+
+        assert_eq!(combined["total"], 1);
+
+        let future = audit_events(&app, &admin_token, "since=2999-01-01T00:00:00Z").await;
+
+        assert_eq!(future["total"], 0);
+
+        let past = audit_events(&app, &admin_token, "until=2000-01-01T00:00:00Z").await;
+
+        assert_eq!(past["total"], 0);
+
+        let paged = audit_events(&app, &admin_token, "action=sign_in&per_page=2").await;
+
+        assert_eq!(paged["total"], 3);
+        assert_eq!(paged["items"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn audit_events_are_for_admins_only() {
+        let app = TestApp::new().await;
+        let (_, alice_token) = app.user("alice").await;
+        let (_, moderator_token) = app.user_with_role("moderator", "moderator").await;
+
+        for access_token in [alice_token, moderator_token] {
+            let response = app
+                .request(
+                    Method::GET,
+                    "/v1/admin/audit-events",
+                    Some(&access_token),
+                    None,
+                )
+                .await;
+
+            assert_eq!(response.status, StatusCode::FORBIDDEN);
+        }
+    }
+
+    async fn status(app: &TestApp, method: Method, uri: &str, access_token: &str) -> StatusCode {
+        app.request(method, uri, Some(access_token), None)
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    async fn users_cannot_reach_the_admin_endpoints() {
+        let app = TestApp::new().await;
+        let (_, alice_token) = app.user("alice").await;
+
+        for uri in ["/v1/admin/users", "/v1/admin/categories"] {
+            assert_eq!(
+                status(&app, Method::GET, uri, &alice_token).await,
+                StatusCode::FORBIDDEN
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn moderators_manage_categories_but_not_users() {
+        let app = TestApp::new().await;
+        let (_, moderator_token) = app.user_with_role("moderator", "moderator").await;
+        let (_, admin_token) = app.user_with_role("admin", "admin").await;
+
+        assert_eq!(
+            status(&app, Method::GET, "/v1/admin/categories", &moderator_token).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, Method::GET, "/v1/admin/users", &moderator_token).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // Admins include the permissions of moderators.
+        assert_eq!(
+            status(&app, Method::GET, "/v1/admin/categories", &admin_token).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, Method::GET, "/v1/admin/users", &admin_token).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn role_changes_revoke_the_tokens_of_the_user() {
+        let app = TestApp::new().await;
+        let (alice, alice_token) = app.user("alice").await;
+        let (_, admin_token) = app.user_with_role("admin", "admin").await;
+
+        assert_eq!(
+            status(&app, Method::GET, "/v1/users/me", &alice_token).await,
+            StatusCode::OK
+        );
+
+        let update = app
+            .request(
+                Method::PUT,
+                &format!("/v1/admin/users/{}/role", alice),
+                Some(&admin_token),
+                Some(json!({ "role": "moderator" })),
+            )
+            .await;
+
+        assert_eq!(update.status, StatusCode::OK);
+        assert_eq!(
+            status(&app, Method::GET, "/v1/users/me", &alice_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Signing in again hands out a token with the new role.
+        let alice_token = app.sign_in("alice").await.body["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        assert_eq!(
+            status(&app, Method::GET, "/v1/admin/categories", &alice_token).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn disabling_revokes_the_tokens_of_the_user() {
+        let app = TestApp::new().await;
+        let (alice, alice_token) = app.user("alice").await;
+        let (_, admin_token) = app.user_with_role("admin", "admin").await;
+
+        assert_eq!(
+            status(&app, Method::GET, "/v1/users/me", &alice_token).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                &app,
+                Method::POST,
+                &format!("/v1/admin/users/{}/disable", alice),
+                &admin_token
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&app, Method::GET, "/v1/users/me", &alice_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::FORBIDDEN);
+
+        assert_eq!(
+            status(
+                &app,
+                Method::POST,
+                &format!("/v1/admin/users/{}/enable", alice),
+                &admin_token
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn admin_actions_are_audited() {
+        let app = TestApp::new().await;
+        let (alice, alice_token) = app.user("alice").await;
+        let (admin, admin_token) = app.user_with_role("admin", "admin").await;
+        let (moderator, moderator_token) = app.user_with_role("moderator", "moderator").await;
+
+        let game = app
+            .request(
+                Method::POST,
+                "/v1/games",
+                Some(&alice_token),
+                Some(json!({ "title": "Hades", "categories": ["roguelike"] })),
+            )
+            .await;
+
+        assert_eq!(game.status, StatusCode::CREATED);
+
+        let categories = app
+            .request(
+                Method::GET,
+                "/v1/admin/categories",
+                Some(&moderator_token),
+                None,
+            )
+            .await;
+        let category = categories.body[0]["id"].as_str().unwrap().to_string();
+        let category_uri = format!("/v1/admin/categories/{}", category);
+
+        let requests = [
+            (
+                Method::PUT,
+                format!("/v1/admin/users/{}/role", alice),
+                Some(json!({ "role": "moderator" })),
+                &admin_token,
+            ),
+            (
+                Method::POST,
+                format!("/v1/admin/users/{}/disable", alice),
+                None,
+                &admin_token,
+            ),
+            (
+                Method::POST,
+                format!("/v1/admin/users/{}/enable", alice),
+                None,
+                &admin_token,
+            ),
+            (
+                Method::POST,
+                format!("/v1/admin/users/{}/unlock", alice),
+                None,
+                &admin_token,
+            ),
+            (
+                Method::PATCH,
+                category_uri.clone(),
+                Some(json!({ "name": "roguelite" })),
+                &moderator_token,
+            ),
+            (Method::DELETE, category_uri, None, &moderator_token),
+        ];
+
+        for (method, uri, body, access_token) in requests {
+            let response = app.request(method, &uri, Some(access_token), body).await;
+
+            assert!(response.status.is_success(), "{}", uri);
+        }
+
+        let expected = [
+            ("user_role_changed", &admin, &alice),
+            ("user_disabled", &admin, &alice),
+            ("user_enabled", &admin, &alice),
+            ("user_unlocked", &admin, &alice),
+            ("category_updated", &moderator, &category),
+            ("category_deleted", &moderator, &category),
+        ];
+
+        for (action, actor_id, target_id) in expected {
+            let events = audit_events(&app, &admin_token, &format!("action={}", action)).await;
+
+            assert_eq!(events["total"], 1, "{}", action);
+            assert_eq!(events["items"][0]["actor_id"], actor_id.as_str());
+            assert_eq!(events["items"][0]["target_id"], target_id.as_str());
+            assert_eq!(events["items"][0]["ip_address"], "127.0.0.1");
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use axum::{
+    routing::{get, patch, post, put},
+    Router,
+};
+use tower_http::auth::AsyncRequireAuthorizationLayer;
+
+use crate::{
+    authentication::{
+        middleware::{JWTAuthorizationLayer, RoleAuthorizationLayer},
+        role::Role,
+    },
+    endpoints::Endpoint,
+};
+
+use super::{processor::AdminProcessor, AdminEndpoint};
+
+impl Endpoint for AdminEndpoint {
+    fn connect_router() -> Router {
+        let users = Router::new()
+            .route("/users", get(AdminProcessor::read_users))
+            .route("/users/:id/role", put(AdminProcessor::update_user_role))
+            .route("/users/:id/disable", post(AdminProcessor::disable_user))
+            .route("/users/:id/enable", post(AdminProcessor::enable_user))
+            .route("/users/:id/unlock", post(AdminProcessor::unlock_user))
+            .route("/audit-events", get(AdminProcessor::read_audit_events))
+            .route_layer(AsyncRequireAuthorizationLayer::new(
+                RoleAuthorizationLayer::new(Role::Admin),
+            ));
+
+        let categories = Router::new()
+            .route("/categories", get(AdminProcessor::read_categories))
+            .route(
+                "/categories/:id",
+                patch(AdminProcessor::update_category).delete(AdminProcessor::delete_category),
+            )
+            .route_layer(AsyncRequireAuthorizationLayer::new(
+                RoleAuthorizationLayer::new(Role::Moderator),
+            ));
+
+        Router::new().nest("/admin", users.merge(categories)).layer(
+            AsyncRequireAuthorizationLayer::new(JWTAuthorizationLayer::default()),
+        )
+    }
+}
@@ -1,7 +1,11 @@
 use std::str::FromStr;
 
+use ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use headers::ETag;
 use serde::{Deserialize, Serialize};
-use sqlx::{Database, Decode, FromRow, Type};
+use sqlx::{sqlite::SqliteRow, Database, Decode, FromRow, Row, Type};
+
+use self::payloads::{GameSortField, SortOrder};
 
 pub mod payloads;
 
@@ -15,6 +19,17 @@ pub enum Status {
     Completed,
 }
 
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Untried => "untried",
+            Status::Progressing => "progressing",
+            Status::Ended => "ended",
+            Status::Completed => "completed",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Categories {
     pub content: Vec<String>,
@@ -53,6 +68,19 @@ where
     }
 }
 
+impl<DB: Database> Type<DB> for Categories
+where
+    str: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <str as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <str as Type<DB>>::compatible(ty)
+    }
+}
+
 #[derive(Clone, Debug, FromRow, Serialize)]
 pub struct Game {
     pub id: String,
@@ -65,6 +93,8 @@ pub struct Game {
     pub note: Option<String>,
     pub created_at: String,
     pub updated_at: Option<String>,
+    /// Number of times the game was written to, starting at 1.
+    pub version: i64,
 }
 
 impl Game {
@@ -79,6 +109,7 @@ impl Game {
         note: Option<String>,
         created_at: String,
         updated_at: Option<String>,
+        version: i64,
     ) -> Self {
         Self {
             id,
@@ -91,6 +122,87 @@ impl Game {
             note,
             created_at,
             updated_at,
+            version,
         }
     }
+
+    /// Strong entity tag of the current version of the game.
+    pub fn entity_tag(&self) -> ETag {
+        entity_tag(self.version)
+    }
+}
+
+pub fn entity_tag(version: i64) -> ETag {
+    format!("\"{}\"", version).parse().unwrap()
+}
+
+/// Game that matched a search, with the matching words wrapped in `<mark>` tags.
+#[derive(Clone, Debug, Serialize)]
+pub struct GameSearchResult {
+    #[serde(flatten)]
+    pub game: Game,
+    pub title_highlight: String,
+    /// Excerpt of the column that matched best.
+    pub snippet: String,
+    /// Lower is more relevant.
+    pub rank: f64,
+}
+
+impl<'r> FromRow<'r, SqliteRow> for GameSearchResult {
+    fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            game: Game::from_row(row)?,
+            title_highlight: row.try_get("title_highlight")?,
+            snippet: row.try_get("snippet")?,
+            rank: row.try_get("rank")?,
+        })
+    }
+}
+
+/// Value of the sort column that a page of games ended on.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SortKey {
+    Integer(i64),
+    Text(String),
+}
+
+/// Position after the last game of a page, handed to clients as an opaque string. It is only
+/// valid for the sort order it was created for.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameCursor {
+    pub sort: GameSortField,
+    pub order: SortOrder,
+    pub key: SortKey,
+    pub id: String,
+}
+
+impl GameCursor {
+    pub fn after(game: &Game, sort: GameSortField, order: SortOrder) -> Self {
+        let key = match sort {
+            GameSortField::Title => SortKey::Text(game.title.clone()),
+            GameSortField::Rating => SortKey::Integer(game.rating.map_or(-1, i64::from)),
+            GameSortField::CreatedAt => SortKey::Text(game.created_at.clone()),
+            GameSortField::UpdatedAt => SortKey::Text(game.updated_at.clone().unwrap_or_default()),
+        };
+
+        Self {
+            sort,
+            order,
+            key,
+            id: game.id.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> Option<String> {
+        let json = serde_json::to_vec(self).ok()?;
+
+        Base64UrlSafeNoPadding::encode_to_string(json).ok()
+    }
+
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let json = Base64UrlSafeNoPadding::decode_to_vec(encoded, None).ok()?;
+
+        serde_json::from_slice(&json).ok()
+    }
 }
@@ -1,4 +1,5 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use super::Status;
 
@@ -12,12 +13,92 @@ pub struct GameCreatePayload {
     pub note: Option<String>,
 }
 
+/// Changes to a game, where a field that is left out stays as it is and one that is `null` is
+/// cleared. The title is the exception, which is rejected as `null`.
 #[derive(Deserialize)]
 pub struct GameUpdatePayload {
-    pub title: String,
-    pub image_url: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub image_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub status: Option<Option<Status>>,
+    #[serde(default, deserialize_with = "present")]
+    pub rating: Option<Option<u8>>,
+    #[serde(default, deserialize_with = "present")]
+    pub categories: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub note: Option<Option<String>>,
+}
+
+/// Wraps the value of a field that is present, even if it is `null`, so that it can be told
+/// apart from one that is left out.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameSortField {
+    Title,
+    Rating,
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Whether games need to be in any or in all of the requested categories.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CategoryMatch {
+    #[default]
+    Any,
+    All,
+}
+
+/// Query parameters of the game listing, all of which are optional.
+#[derive(Deserialize)]
+pub struct GameListQuery {
+    pub limit: Option<u32>,
+    /// Cursor returned as `next_cursor` with the previous page.
+    pub after: Option<String>,
     pub status: Option<Status>,
-    pub rating: Option<u8>,
-    pub categories: Option<Vec<String>>,
-    pub note: Option<String>,
+    pub rating_min: Option<u8>,
+    pub rating_max: Option<u8>,
+    /// Comma-separated category names.
+    pub categories: Option<String>,
+    #[serde(default)]
+    pub category_match: CategoryMatch,
+    /// Case-insensitive substring of the title.
+    pub title: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub sort: GameSortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+/// Query parameters of the game search.
+#[derive(Deserialize)]
+pub struct GameSearchQuery {
+    /// Words to look for, where `"quoted words"` form a phrase and a trailing `*` matches
+    /// every word that starts with what comes before it.
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<u32>,
 }
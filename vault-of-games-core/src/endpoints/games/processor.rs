@@ -1,33 +1,85 @@
 use anyhow::Result;
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
+    extract::{Extension, Path, Query, TypedHeader},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
 use chrono::Utc;
-use jwt_simple::prelude::{JWTClaims, NoCustomClaims};
-use sqlx::{query, query_as, sqlite::SqliteQueryResult};
+use headers::{HeaderMapExt, IfMatch};
+use sqlx::{
+    query,
+    query::QueryAs,
+    query_as,
+    sqlite::{SqliteArguments, SqliteQueryResult},
+    Executor, Sqlite, Transaction,
+};
 use uuid::Uuid;
 
 use crate::{
+    authentication::claims::AuthorizationClaims,
     database::DatabaseConnectionPool,
     endpoints::games::entities::{Categories, Status},
     error::ProcessorError,
+    pagination::{CursorPage, DEFAULT_PER_PAGE, MAX_PER_PAGE},
+    validation::ValidationErrors,
 };
 
 use super::entities::{
-    payloads::{GameCreatePayload, GameUpdatePayload},
-    Game,
+    entity_tag,
+    payloads::{
+        CategoryMatch, GameCreatePayload, GameListQuery, GameSearchQuery, GameSortField,
+        GameUpdatePayload, SortOrder,
+    },
+    Game, GameCursor, GameSearchResult, SortKey,
 };
 
+#[derive(Clone, Debug)]
+enum FilterArgument {
+    Integer(i64),
+    Text(String),
+}
+
+/// `WHERE` conditions of a listing that is put together at runtime, along with the arguments
+/// of their placeholders in order.
+#[derive(Default)]
+struct ListingFilter {
+    conditions: Vec<String>,
+    arguments: Vec<FilterArgument>,
+}
+
+impl ListingFilter {
+    fn push(&mut self, condition: impl Into<String>, arguments: Vec<FilterArgument>) {
+        self.conditions.push(condition.into());
+        self.arguments.extend(arguments);
+    }
+
+    fn conditions(&self) -> String {
+        self.conditions.join(" AND ")
+    }
+
+    fn bind_to<'q, O>(
+        &self,
+        mut query: QueryAs<'q, Sqlite, O, SqliteArguments<'q>>,
+    ) -> QueryAs<'q, Sqlite, O, SqliteArguments<'q>> {
+        for argument in self.arguments.iter().cloned() {
+            query = match argument {
+                FilterArgument::Integer(argument) => query.bind(argument),
+                FilterArgument::Text(argument) => query.bind(argument),
+            };
+        }
+
+        query
+    }
+}
+
 #[derive(Default)]
 pub struct GamesProcessor;
 
 impl GamesProcessor {
     pub async fn create(
         Json(payload): Json<GameCreatePayload>,
-        Extension(claims): Extension<JWTClaims<NoCustomClaims>>,
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         let game = Game::new(
@@ -41,12 +93,15 @@ impl GamesProcessor {
             payload.note,
             Utc::now().to_string(),
             None,
+            1,
         );
 
+        let mut transaction = pool.begin().await?;
+
         query!(
             "
-            INSERT INTO games (id, user_id, title, image_url, status, rating, note, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);
+            INSERT INTO games (id, user_id, title, image_url, status, rating, note, created_at, updated_at, version)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);
             ",
             game.id,
             game.user_id,
@@ -57,62 +112,53 @@ impl GamesProcessor {
             game.note,
             game.created_at,
             game.updated_at,
+            game.version,
         )
-        .execute(&pool)
+        .execute(&mut transaction)
         .await?;
 
         if let Some(categories) = &game.categories {
-            for category in categories.content.iter() {
-                let record = query!(
-                    "
-                    SELECT id
-                    FROM categories
-                    WHERE name = ?;
-                    ",
-                    category
-                )
-                .fetch_optional(&pool)
-                .await?;
-
-                let category_id = match record {
-                    Some(record) => record.id,
-                    None => Some(Uuid::new_v4().to_string()),
-                };
-
-                query!(
-                    "
-                    INSERT OR IGNORE INTO categories (id, name)
-                    VALUES (?1, ?2);
-                    ",
-                    category_id,
-                    category,
-                )
-                .execute(&pool)
-                .await?;
-
-                query!(
-                    "
-                    INSERT INTO games_categories (game_id, category_id)
-                    VALUES (?1, ?2);
-                    ",
-                    game.id,
-                    category_id,
-                )
-                .execute(&pool)
-                .await?;
-            }
+            Self::link_categories(&mut transaction, &game.id, &categories.content).await?;
         }
 
-        Ok((StatusCode::CREATED, Json(game)))
+        transaction.commit().await?;
+
+        Ok((
+            StatusCode::CREATED,
+            Self::entity_tag_header(&game),
+            Json(game),
+        ))
     }
 
     pub async fn read(
         Path(id): Path<String>,
-        Extension(claims): Extension<JWTClaims<NoCustomClaims>>,
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         let user_id = claims.subject.unwrap();
+        let game = Self::find_game(&pool, &id, &user_id).await?;
 
+        Ok((Self::entity_tag_header(&game), Json(game)))
+    }
+
+    /// Lets clients make later changes conditional on the game still being at this version.
+    fn entity_tag_header(game: &Game) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(game.entity_tag());
+
+        headers
+    }
+
+    /// Games without categories come with an empty list of them. Takes a transaction as well, to
+    /// read back a change before it is committed.
+    async fn find_game<'e, E>(
+        executor: E,
+        id: &'e str,
+        user_id: &'e str,
+    ) -> Result<Game, ProcessorError>
+    where
+        E: 'e + Executor<'e, Database = Sqlite>,
+    {
         let game = query_as!(
             Game,
             r#"
@@ -122,94 +168,485 @@ impl GamesProcessor {
                 g.image_url as "image_url?",
                 g.status as "status?: Status",
                 g.rating as "rating?: u8",
-                json_group_array(json_quote(c.name)) as "categories?: Categories",
+                json_group_array(c.name) FILTER (WHERE c.name IS NOT NULL) as "categories?: Categories",
                 g.note as "note?",
                 g.created_at as "created_at!: String",
-                g.updated_at as "updated_at?: String"
+                g.updated_at as "updated_at?: String",
+                g.version as "version!: i64"
             FROM games g
-                    JOIN games_categories gc on g.id = gc.game_id
-                    JOIN categories c on c.id = gc.category_id
-            WHERE g.id = ?1 AND g.user_id = ?2;
+                    LEFT JOIN games_categories gc on g.id = gc.game_id
+                    LEFT JOIN categories c on c.id = gc.category_id
+            WHERE g.id = ?1 AND g.user_id = ?2
+            GROUP BY g.id;
             "#,
             id,
             user_id,
         )
-        .fetch_one(&pool)
+        .fetch_one(executor)
         .await?;
 
-        if game.id.is_empty() {
-            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
-        }
-
-        Ok(Json(game))
+        Ok(game)
     }
 
+    /// Lists the games of the user a page at a time, continuing after the cursor of the previous
+    /// page.
     pub async fn read_all(
-        Extension(claims): Extension<JWTClaims<NoCustomClaims>>,
+        Query(listing): Query<GameListQuery>,
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         let user_id = claims.subject.unwrap();
+        let mut errors = ValidationErrors::new();
 
-        let games = query_as!(
-            Game,
-            r#"
-            SELECT g.id as "id!",
-                g.user_id as "user_id!",
-                g.title as "title!",
-                g.image_url as "image_url?",
-                g.status as "status?: Status",
-                g.rating as "rating?: u8",
-                json_group_array(json_quote(c.name)) as "categories?: Categories",
-                g.note as "note?",
-                g.created_at as "created_at!: String",
-                g.updated_at as "updated_at?: String"
+        let cursor = match listing.after.as_deref().map(GameCursor::decode) {
+            Some(Some(cursor)) if cursor.sort == listing.sort && cursor.order == listing.order => {
+                Some(cursor)
+            }
+            Some(_) => {
+                errors.add("after", "is not a cursor of this listing");
+
+                None
+            }
+            None => None,
+        };
+
+        if let (Some(rating_min), Some(rating_max)) = (listing.rating_min, listing.rating_max) {
+            if rating_min > rating_max {
+                errors.add("rating_min", "must not be greater than rating_max");
+            }
+        }
+
+        errors.into_result()?;
+
+        let limit = listing
+            .limit
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+        let mut filter = Self::listing_filter(&user_id, &listing);
+
+        let count = format!(
+            "
+            SELECT COUNT(*)
             FROM games g
-                    JOIN games_categories gc on g.id = gc.game_id
-                    JOIN categories c on c.id = gc.category_id
-            WHERE user_id = ?
-            GROUP BY g.id, g.title, g.image_url, g.status, g.rating, g.note, g.created_at, g.updated_at
-            ORDER BY g.created_at DESC;
-            "#,
-            user_id,
+            WHERE {};
+            ",
+            filter.conditions()
+        );
+
+        let (total,) = filter
+            .bind_to(query_as::<_, (i64,)>(&count))
+            .fetch_one(&pool)
+            .await?;
+
+        let sort_expression = Self::sort_expression(listing.sort);
+        let direction = match listing.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+
+        if let Some(cursor) = cursor {
+            let comparison = match listing.order {
+                SortOrder::Asc => ">",
+                SortOrder::Desc => "<",
+            };
+            let key = match cursor.key {
+                SortKey::Integer(key) => FilterArgument::Integer(key),
+                SortKey::Text(key) => FilterArgument::Text(key),
+            };
+
+            filter.push(
+                format!(
+                    "({0} {1} ? OR ({0} = ? AND g.id {1} ?))",
+                    sort_expression, comparison
+                ),
+                vec![key.clone(), key, FilterArgument::Text(cursor.id)],
+            );
+        }
+
+        // One game more than asked for tells whether there is another page.
+        let listing_query = format!(
+            "
+            SELECT g.id as id,
+                g.user_id as user_id,
+                g.title as title,
+                g.image_url as image_url,
+                g.status as status,
+                g.rating as rating,
+                json_group_array(c.name) FILTER (WHERE c.name IS NOT NULL) as categories,
+                g.note as note,
+                g.created_at as created_at,
+                g.updated_at as updated_at,
+                g.version as version
+            FROM games g
+                    LEFT JOIN games_categories gc on g.id = gc.game_id
+                    LEFT JOIN categories c on c.id = gc.category_id
+            WHERE {conditions}
+            GROUP BY g.id, g.title, g.image_url, g.status, g.rating, g.note, g.created_at, g.updated_at, g.version
+            ORDER BY {sort} {direction}, g.id {direction}
+            LIMIT {limit};
+            ",
+            conditions = filter.conditions(),
+            sort = sort_expression,
+            direction = direction,
+            limit = limit + 1,
+        );
+
+        let mut games = filter
+            .bind_to(query_as::<_, Game>(&listing_query))
+            .fetch_all(&pool)
+            .await?;
+
+        let next_cursor = if games.len() > limit as usize {
+            games.truncate(limit as usize);
+
+            games
+                .last()
+                .and_then(|game| GameCursor::after(game, listing.sort, listing.order).encode())
+        } else {
+            None
+        };
+
+        Ok(Json(CursorPage {
+            items: games,
+            next_cursor,
+            total,
+        }))
+    }
+
+    /// Conditions that a game has to meet to be listed, regardless of the page.
+    fn listing_filter(user_id: &str, listing: &GameListQuery) -> ListingFilter {
+        let mut filter = ListingFilter::default();
+
+        filter.push(
+            "g.user_id = ?",
+            vec![FilterArgument::Text(user_id.to_string())],
+        );
+
+        if let Some(status) = listing.status {
+            filter.push(
+                "g.status = ?",
+                vec![FilterArgument::Text(status.as_str().to_string())],
+            );
+        }
+
+        if let Some(rating_min) = listing.rating_min {
+            filter.push(
+                "g.rating >= ?",
+                vec![FilterArgument::Integer(rating_min.into())],
+            );
+        }
+
+        if let Some(rating_max) = listing.rating_max {
+            filter.push(
+                "g.rating <= ?",
+                vec![FilterArgument::Integer(rating_max.into())],
+            );
+        }
+
+        if let Some(title) = listing.title.as_deref().filter(|title| !title.is_empty()) {
+            filter.push(
+                "instr(lower(g.title), lower(?)) > 0",
+                vec![FilterArgument::Text(title.to_string())],
+            );
+        }
+
+        // Timestamps are stored as text that starts with `YYYY-MM-DD HH:MM:SS`, so they compare
+        // correctly against bounds of that shape.
+        let date_ranges = [
+            ("g.created_at >= ?", listing.created_after),
+            ("g.created_at < ?", listing.created_before),
+            ("g.updated_at >= ?", listing.updated_after),
+            ("g.updated_at < ?", listing.updated_before),
+        ];
+
+        for (condition, bound) in date_ranges {
+            if let Some(bound) = bound {
+                filter.push(
+                    condition,
+                    vec![FilterArgument::Text(
+                        bound.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    )],
+                );
+            }
+        }
+
+        let mut categories: Vec<String> = listing
+            .categories
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|category| category.trim().to_string())
+            .filter(|category| !category.is_empty())
+            .collect();
+
+        categories.sort();
+        categories.dedup();
+
+        if !categories.is_empty() {
+            // Matching in a subquery keeps the other categories of a game in the listing.
+            let placeholders = vec!["?"; categories.len()].join(", ");
+            let mut arguments: Vec<FilterArgument> = categories
+                .iter()
+                .cloned()
+                .map(FilterArgument::Text)
+                .collect();
+
+            let grouping = match listing.category_match {
+                CategoryMatch::Any => String::new(),
+                CategoryMatch::All => {
+                    arguments.push(FilterArgument::Integer(categories.len() as i64));
+
+                    "GROUP BY gc2.game_id HAVING COUNT(DISTINCT c2.name) = ?".to_string()
+                }
+            };
+
+            filter.push(
+                format!(
+                    "g.id IN (SELECT gc2.game_id FROM games_categories gc2 JOIN categories c2 on c2.id = gc2.category_id WHERE c2.name IN ({}) {})",
+                    placeholders, grouping
+                ),
+                arguments,
+            );
+        }
+
+        filter
+    }
+
+    /// Games without a rating or an update sort before all others in ascending order.
+    fn sort_expression(sort: GameSortField) -> &'static str {
+        match sort {
+            GameSortField::Title => "g.title COLLATE NOCASE",
+            GameSortField::Rating => "COALESCE(g.rating, -1)",
+            GameSortField::CreatedAt => "g.created_at",
+            GameSortField::UpdatedAt => "COALESCE(g.updated_at, '')",
+        }
+    }
+
+    /// Looks for games of the user by the words in their title, note and categories, most
+    /// relevant first.
+    pub async fn search(
+        Query(search): Query<GameSearchQuery>,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let expression = match Self::match_expression(&search.q) {
+            Some(expression) => expression,
+            None => {
+                let mut errors = ValidationErrors::new();
+                errors.add("q", "must contain at least one word");
+
+                return Err(errors.into());
+            }
+        };
+
+        let limit = search
+            .limit
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+
+        // Matches in the title weigh the most, then those in categories and then in the note.
+        let games = query_as::<_, GameSearchResult>(
+            "
+            WITH matches AS (
+                SELECT game_id,
+                    highlight(games_search, 2, '<mark>', '</mark>') as title_highlight,
+                    snippet(games_search, -1, '<mark>', '</mark>', '…', 12) as snippet,
+                    bm25(games_search, 0.0, 0.0, 10.0, 2.0, 5.0) as rank
+                FROM games_search
+                WHERE games_search MATCH ?1 AND user_id = ?2
+                ORDER BY rank
+                LIMIT ?3
+            )
+            SELECT g.id as id,
+                g.user_id as user_id,
+                g.title as title,
+                g.image_url as image_url,
+                g.status as status,
+                g.rating as rating,
+                json_group_array(c.name) FILTER (WHERE c.name IS NOT NULL) as categories,
+                g.note as note,
+                g.created_at as created_at,
+                g.updated_at as updated_at,
+                g.version as version,
+                m.title_highlight as title_highlight,
+                m.snippet as snippet,
+                m.rank as rank
+            FROM matches m
+                    JOIN games g on g.id = m.game_id
+                    LEFT JOIN games_categories gc on g.id = gc.game_id
+                    LEFT JOIN categories c on c.id = gc.category_id
+            WHERE g.user_id = ?2
+            GROUP BY g.id, m.title_highlight, m.snippet, m.rank
+            ORDER BY m.rank, g.id;
+            ",
         )
+        .bind(expression)
+        .bind(&user_id)
+        .bind(i64::from(limit))
         .fetch_all(&pool)
         .await?;
 
         Ok(Json(games))
     }
 
+    /// Turns a search as typed by the user into an FTS5 query, so that its syntax cannot be used
+    /// to look at other columns or to make the query fail. Every word and `"quoted phrase"` has
+    /// to match, and a trailing `*` makes either match as a prefix.
+    fn match_expression(search: &str) -> Option<String> {
+        let mut terms = Vec::new();
+        let mut characters = search.chars().peekable();
+
+        while let Some(&character) = characters.peek() {
+            if character.is_whitespace() {
+                characters.next();
+                continue;
+            }
+
+            let mut term = String::new();
+
+            if character == '"' {
+                characters.next();
+
+                for character in characters.by_ref() {
+                    if character == '"' {
+                        break;
+                    }
+
+                    term.push(character);
+                }
+            } else {
+                while let Some(&character) = characters.peek() {
+                    if character.is_whitespace() || character == '"' {
+                        break;
+                    }
+
+                    term.push(character);
+                    characters.next();
+                }
+            }
+
+            let mut prefix = characters.peek() == Some(&'*');
+
+            if prefix {
+                characters.next();
+            }
+
+            let term = term.trim();
+            let stripped = term.trim_end_matches('*');
+            prefix |= stripped.len() != term.len();
+
+            if !stripped.chars().any(char::is_alphanumeric) {
+                continue;
+            }
+
+            terms.push(format!(
+                "\"{}\"{}",
+                stripped.replace('"', "\"\""),
+                if prefix { " *" } else { "" }
+            ));
+        }
+
+        if terms.is_empty() {
+            None
+        } else {
+            Some(terms.join(" "))
+        }
+    }
+
+    /// Changes only the fields that are present in the payload. With `If-Match`, the change is
+    /// only made if the game is still at one of the given versions.
     pub async fn update(
         Path(id): Path<String>,
+        if_match: Option<TypedHeader<IfMatch>>,
         Json(payload): Json<GameUpdatePayload>,
-        Extension(claims): Extension<JWTClaims<NoCustomClaims>>,
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         let user_id = claims.subject.unwrap();
 
+        // Left out, the title stays as it is, but it cannot be cleared like the other fields.
+        let title = match payload.title {
+            Some(None) => {
+                let mut errors = ValidationErrors::new();
+                errors.add("title", "must not be null");
+
+                return Err(errors.into());
+            }
+            title => title.flatten(),
+        };
+
+        let mut transaction = pool.begin().await?;
+
+        let current = query!(
+            r#"
+            SELECT version as "version!: i64"
+            FROM games
+            WHERE id = ?1 AND user_id = ?2;
+            "#,
+            id,
+            user_id,
+        )
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or(ProcessorError::DatabaseError(sqlx::Error::RowNotFound))?;
+
+        // Without a precondition, the last write wins.
+        let expected_version = match if_match {
+            Some(TypedHeader(if_match)) => {
+                if !if_match.precondition_passes(&entity_tag(current.version)) {
+                    return Err(ProcessorError::PreconditionFailed);
+                }
+
+                Some(current.version)
+            }
+            None => None,
+        };
+
+        let set_image_url = payload.image_url.is_some();
+        let image_url = payload.image_url.flatten();
+        let set_status = payload.status.is_some();
+        let status = payload.status.flatten();
+        let set_rating = payload.rating.is_some();
+        let rating = payload.rating.flatten();
+        let set_note = payload.note.is_some();
+        let note = payload.note.flatten();
+
         let game = query!(
             "
             UPDATE games
-            SET title      = ?1,
-                image_url  = ?2,
-                status     = ?3,
-                rating     = ?4,
-                note       = ?5,
+            SET title      = COALESCE(?1, title),
+                image_url  = CASE WHEN ?2 THEN ?3 ELSE image_url END,
+                status     = CASE WHEN ?4 THEN ?5 ELSE status END,
+                rating     = CASE WHEN ?6 THEN ?7 ELSE rating END,
+                note       = CASE WHEN ?8 THEN ?9 ELSE note END,
+                version    = version + 1,
                 updated_at = CURRENT_TIMESTAMP
-            WHERE id = ?6 AND user_id = ?7;
+            WHERE id = ?10 AND user_id = ?11 AND (?12 IS NULL OR version = ?12);
             ",
-            payload.title,
-            payload.image_url,
-            payload.status,
-            payload.rating,
-            payload.note,
+            title,
+            set_image_url,
+            image_url,
+            set_status,
+            status,
+            set_rating,
+            rating,
+            set_note,
+            note,
             id,
             user_id,
+            expected_version,
         )
-        .execute(&pool)
+        .execute(&mut transaction)
         .await?;
 
+        // Another write got in between reading the version and making the change.
         if game.rows_affected() == 0 {
-            return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
+            return Err(match expected_version {
+                Some(_) => ProcessorError::PreconditionFailed,
+                None => ProcessorError::DatabaseError(sqlx::Error::RowNotFound),
+            });
         }
 
         if let Some(categories) = payload.categories {
@@ -221,66 +658,73 @@ impl GamesProcessor {
                 ",
                 id,
             )
-            .execute(&pool)
+            .execute(&mut transaction)
             .await?;
 
-            for category in categories {
-                let record = query!(
-                    "
-                    SELECT id
-                    FROM categories
-                    WHERE name = ?;
-                    ",
-                    category
-                )
-                .fetch_optional(&pool)
-                .await?;
-
-                if let Some(record) = record {
-                    query!(
-                        "
-                        INSERT INTO games_categories (game_id, category_id)
-                        VALUES (?1, ?2);
-                        ",
-                        id,
-                        record.id,
-                    )
-                    .execute(&pool)
-                    .await?;
-                } else {
-                    let category_id = Uuid::new_v4().to_string();
-
-                    query!(
-                        "
-                        INSERT OR IGNORE INTO categories (id, name)
-                        VALUES (?1, ?2);
-                        ",
-                        category_id,
-                        category,
-                    )
-                    .execute(&pool)
-                    .await?;
-
-                    query!(
-                        "
-                        INSERT INTO games_categories (game_id, category_id)
-                        VALUES (?1, ?2);
-                        ",
-                        id,
-                        category_id,
-                    )
-                    .execute(&pool)
-                    .await?;
-                }
-            }
+            Self::link_categories(&mut transaction, &id, &categories.unwrap_or_default()).await?;
+        }
+
+        // Read back before committing, so that the response shows this change and no later one.
+        let game = Self::find_game(&mut transaction, &id, &user_id).await?;
+
+        transaction.commit().await?;
+
+        Ok((Self::entity_tag_header(&game), Json(game)))
+    }
+
+    /// Puts the game into the categories, creating those that do not exist yet. Runs as part of
+    /// the write of the game, so that a failure leaves neither the game nor the links behind.
+    async fn link_categories(
+        transaction: &mut Transaction<'_, Sqlite>,
+        game_id: &str,
+        categories: &[String],
+    ) -> Result<(), ProcessorError> {
+        for category in categories {
+            let record = query!(
+                "
+                SELECT id
+                FROM categories
+                WHERE name = ?;
+                ",
+                category
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            let category_id = match record {
+                Some(record) => record.id,
+                None => Some(Uuid::new_v4().to_string()),
+            };
+
+            query!(
+                "
+                INSERT OR IGNORE INTO categories (id, name)
+                VALUES (?1, ?2);
+                ",
+                category_id,
+                category,
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            query!(
+                "
+                INSERT INTO games_categories (game_id, category_id)
+                VALUES (?1, ?2);
+                ",
+                game_id,
+                category_id,
+            )
+            .execute(&mut *transaction)
+            .await?;
         }
 
-        Ok(StatusCode::OK)
+        Ok(())
     }
 
     pub async fn delete(
         Path(id): Path<String>,
-        Extension(claims): Extension<JWTClaims<NoCustomClaims>>,
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         let user_id = claims.subject.unwrap();
@@ -304,3 +748,602 @@ impl GamesProcessor {
         Ok(StatusCode::NO_CONTENT)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH},
+        Body, Method, Request, StatusCode,
+    };
+    use serde_json::{json, Value};
+
+    use super::GamesProcessor;
+    use crate::testing::{TestApp, TestResponse};
+
+    async fn create_game(app: &TestApp, access_token: &str, game: Value) -> String {
+        let response = app
+            .request(Method::POST, "/v1/games", Some(access_token), Some(game))
+            .await;
+
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        response.body["id"].as_str().unwrap().to_string()
+    }
+
+    async fn list(app: &TestApp, access_token: &str, query: &str) -> Value {
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/v1/games?{}", query),
+                Some(access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        response.body
+    }
+
+    fn ids(page: &Value) -> Vec<String> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|game| game["id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn categories(game: &Value) -> Vec<String> {
+        let mut categories: Vec<String> = game["categories"]["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|category| category.as_str().unwrap().to_string())
+            .collect();
+
+        categories.sort();
+        categories
+    }
+
+    /// Games without categories used to come back as `[null]`, which failed to decode.
+    #[tokio::test]
+    async fn games_come_with_their_categories_however_many() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let cases = [
+            ("Tetris", vec![]),
+            ("Celeste", vec!["platformer"]),
+            ("Hades", vec!["action", "indie", "roguelike"]),
+        ];
+        let mut expected = Vec::new();
+
+        for (title, game_categories) in cases.iter() {
+            let id = create_game(
+                &app,
+                &access_token,
+                json!({ "title": title, "categories": game_categories }),
+            )
+            .await;
+
+            expected.push((id, game_categories.clone()));
+        }
+
+        // Games created before categories were optional have no links at all.
+        let user_id: String = sqlx::query_scalar("SELECT id FROM users WHERE username = 'alice';")
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        sqlx::query(
+            "INSERT INTO games (id, user_id, title, created_at) VALUES ('legacy', ?, 'Pong', '2021-11-20 20:29:33');",
+        )
+        .bind(&user_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        expected.push(("legacy".to_string(), vec![]));
+
+        for (id, game_categories) in expected.iter() {
+            let game = app
+                .request(
+                    Method::GET,
+                    &format!("/v1/games/{}", id),
+                    Some(&access_token),
+                    None,
+                )
+                .await;
+
+            assert_eq!(game.status, StatusCode::OK);
+            assert_eq!(&categories(&game.body), game_categories);
+        }
+
+        let listing = list(&app, &access_token, "limit=100").await;
+        let export = app
+            .request(
+                Method::GET,
+                "/v1/users/me/export",
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(export.status, StatusCode::OK);
+
+        for games in [&listing["items"], &export.body["games"]] {
+            let games = games.as_array().unwrap();
+
+            assert_eq!(games.len(), expected.len());
+
+            for (id, game_categories) in expected.iter() {
+                let game = games.iter().find(|game| game["id"] == id.as_str()).unwrap();
+
+                assert_eq!(&categories(game), game_categories);
+            }
+        }
+    }
+
+    /// Makes linking a game to the category fail, after the game itself has been written.
+    async fn break_category(app: &TestApp, name: &str) {
+        sqlx::query(&format!(
+            "
+            CREATE TRIGGER broken_category
+                BEFORE INSERT
+                ON games_categories
+                WHEN (SELECT name FROM categories WHERE id = NEW.category_id) = '{}'
+            BEGIN
+                SELECT RAISE(ABORT, 'injected failure');
+            END;
+            ",
+            name
+        ))
+        .execute(&app.pool)
+        .await
+        .unwrap();
+    }
+
+    async fn count(app: &TestApp, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {};", table))
+            .fetch_one(&app.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_creations_leave_nothing_behind() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        break_category(&app, "broken").await;
+
+        let response = app
+            .request(
+                Method::POST,
+                "/v1/games",
+                Some(&access_token),
+                Some(json!({ "title": "Hades", "categories": ["roguelike", "broken"] })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        for table in ["games", "games_categories", "categories", "games_search"] {
+            assert_eq!(count(&app, table).await, 0, "rows left in {}", table);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_updates_leave_the_game_as_it_was() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let id = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Hades", "categories": ["roguelike"] }),
+        )
+        .await;
+        break_category(&app, "broken").await;
+
+        let response = app
+            .request(
+                Method::PATCH,
+                &format!("/v1/games/{}", id),
+                Some(&access_token),
+                Some(json!({ "title": "Hades II", "categories": ["action", "broken"] })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let game = app
+            .request(
+                Method::GET,
+                &format!("/v1/games/{}", id),
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(game.body["title"], "Hades");
+        assert_eq!(game.body["version"], 1);
+        assert_eq!(categories(&game.body), ["roguelike"]);
+        assert_eq!(count(&app, "categories").await, 1);
+    }
+
+    async fn update_game(
+        app: &TestApp,
+        access_token: &str,
+        id: &str,
+        if_match: Option<&str>,
+        changes: Value,
+    ) -> TestResponse {
+        let mut request = Request::builder()
+            .method(Method::PATCH)
+            .uri(format!("/v1/games/{}", id))
+            .header(AUTHORIZATION, format!("Bearer {}", access_token))
+            .header(CONTENT_TYPE, "application/json");
+
+        if let Some(if_match) = if_match {
+            request = request.header(IF_MATCH, if_match);
+        }
+
+        app.send(request.body(Body::from(changes.to_string())).unwrap())
+            .await
+    }
+
+    #[tokio::test]
+    async fn updates_change_only_the_fields_present() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let id = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Hades", "rating": 9, "note": "One more run" }),
+        )
+        .await;
+
+        let response = update_game(&app, &access_token, &id, None, json!({ "note": null })).await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body["title"], "Hades");
+        assert_eq!(response.body["rating"], 9);
+        assert!(response.body["note"].is_null());
+        assert_eq!(response.body["version"], 2);
+        assert_eq!(response.headers[ETAG], "\"2\"");
+
+        let response = update_game(&app, &access_token, &id, None, json!({ "title": null })).await;
+
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let game = app
+            .request(
+                Method::GET,
+                &format!("/v1/games/{}", id),
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(game.body["title"], "Hades");
+        assert_eq!(game.body["version"], 2);
+    }
+
+    #[tokio::test]
+    async fn updates_with_a_stale_version_are_rejected() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let id = create_game(&app, &access_token, json!({ "title": "Hades" })).await;
+
+        let first = update_game(
+            &app,
+            &access_token,
+            &id,
+            Some("\"1\""),
+            json!({ "rating": 9 }),
+        )
+        .await;
+
+        assert_eq!(first.status, StatusCode::OK);
+        assert_eq!(first.body["version"], 2);
+
+        // Made by a client that has not seen the first update yet.
+        let second = update_game(
+            &app,
+            &access_token,
+            &id,
+            Some("\"1\""),
+            json!({ "rating": 3 }),
+        )
+        .await;
+
+        assert_eq!(second.status, StatusCode::PRECONDITION_FAILED);
+
+        let game = app
+            .request(
+                Method::GET,
+                &format!("/v1/games/{}", id),
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(game.body["rating"], 9);
+        assert_eq!(game.headers[ETAG], "\"2\"");
+    }
+
+    #[tokio::test]
+    async fn cursors_skip_nothing_and_repeat_nothing_across_ties() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+
+        // Equal ratings leave the order of the games to the tiebreaker.
+        for title in ["Celeste", "Hades", "Inside", "Limbo", "Tunic"] {
+            create_game(&app, &access_token, json!({ "title": title, "rating": 9 })).await;
+        }
+
+        let everything = ids(&list(&app, &access_token, "sort=rating&limit=100").await);
+        let mut paged = Vec::new();
+        let mut query = "sort=rating&limit=2".to_string();
+
+        loop {
+            let page = list(&app, &access_token, &query).await;
+
+            assert_eq!(page["total"], 5);
+
+            paged.extend(ids(&page));
+
+            match page["next_cursor"].as_str() {
+                Some(cursor) => query = format!("sort=rating&limit=2&after={}", cursor),
+                None => break,
+            }
+        }
+
+        assert_eq!(paged, everything);
+    }
+
+    #[tokio::test]
+    async fn cursors_are_stable_when_games_are_added() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+
+        for title in ["Celeste", "Hades", "Inside", "Limbo"] {
+            create_game(&app, &access_token, json!({ "title": title })).await;
+        }
+
+        let first = list(&app, &access_token, "sort=title&order=asc&limit=2").await;
+
+        assert_eq!(first["items"][1]["title"], "Hades");
+
+        // Sorts before everything on the first page, which would shift an offset by one.
+        create_game(&app, &access_token, json!({ "title": "Abzu" })).await;
+
+        let second = list(
+            &app,
+            &access_token,
+            &format!(
+                "sort=title&order=asc&limit=2&after={}",
+                first["next_cursor"].as_str().unwrap()
+            ),
+        )
+        .await;
+
+        let titles: Vec<&str> = second["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|game| game["title"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(titles, ["Inside", "Limbo"]);
+        assert!(second["next_cursor"].is_null());
+    }
+
+    #[tokio::test]
+    async fn cursors_of_another_sort_are_rejected() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+
+        for title in ["Celeste", "Hades"] {
+            create_game(&app, &access_token, json!({ "title": title })).await;
+        }
+
+        let page = list(&app, &access_token, "sort=title&limit=1").await;
+        let response = app
+            .request(
+                Method::GET,
+                &format!(
+                    "/v1/games?sort=rating&limit=1&after={}",
+                    page["next_cursor"].as_str().unwrap()
+                ),
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn categories_match_any_or_all() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let both = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Hades", "categories": ["roguelike", "action"] }),
+        )
+        .await;
+        let roguelike = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Spelunky", "categories": ["roguelike"] }),
+        )
+        .await;
+        let action = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Doom", "categories": ["action"] }),
+        )
+        .await;
+        create_game(&app, &access_token, json!({ "title": "Tetris" })).await;
+
+        let mut any =
+            ids(&list(&app, &access_token, "categories=roguelike,action&limit=100").await);
+        let mut expected = vec![both.clone(), roguelike.clone(), action];
+
+        any.sort();
+        expected.sort();
+
+        assert_eq!(any, expected);
+
+        let all = list(
+            &app,
+            &access_token,
+            "categories=roguelike,action&category_match=all",
+        )
+        .await;
+
+        assert_eq!(all["total"], 1);
+        assert_eq!(ids(&all), [both.clone()]);
+        // The other categories of a matching game are still listed.
+        assert_eq!(categories(&all["items"][0]), ["action", "roguelike"]);
+
+        // Repeating a category does not raise the number of them that have to match.
+        let mut repeated = ids(&list(
+            &app,
+            &access_token,
+            "categories=roguelike,roguelike&category_match=all",
+        )
+        .await);
+        let mut expected = vec![both, roguelike];
+
+        repeated.sort();
+        expected.sort();
+
+        assert_eq!(repeated, expected);
+    }
+
+    async fn search(app: &TestApp, access_token: &str, q: &str) -> Vec<String> {
+        let response = app
+            .request(
+                Method::GET,
+                &format!("/v1/games/search?q={}", q),
+                Some(access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        response
+            .body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|game| game["title"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn the_search_index_follows_categories() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let (_, moderator_token) = app.user_with_role("moderator", "moderator").await;
+        let id = create_game(
+            &app,
+            &access_token,
+            json!({ "title": "Hades", "categories": ["roguelike"] }),
+        )
+        .await;
+
+        assert_eq!(search(&app, &access_token, "roguelike").await, ["Hades"]);
+
+        let categories = app
+            .request(
+                Method::GET,
+                "/v1/admin/categories",
+                Some(&moderator_token),
+                None,
+            )
+            .await;
+        let category_id = categories.body[0]["id"].as_str().unwrap();
+
+        let rename = app
+            .request(
+                Method::PATCH,
+                &format!("/v1/admin/categories/{}", category_id),
+                Some(&moderator_token),
+                Some(json!({ "name": "roguelite" })),
+            )
+            .await;
+
+        assert_eq!(rename.status, StatusCode::OK);
+        assert!(search(&app, &access_token, "roguelike").await.is_empty());
+        assert_eq!(search(&app, &access_token, "roguelite").await, ["Hades"]);
+
+        let update = app
+            .request(
+                Method::PATCH,
+                &format!("/v1/games/{}", id),
+                Some(&access_token),
+                Some(json!({ "categories": ["action"] })),
+            )
+            .await;
+
+        assert_eq!(update.status, StatusCode::OK);
+        assert!(search(&app, &access_token, "roguelite").await.is_empty());
+        assert_eq!(search(&app, &access_token, "action").await, ["Hades"]);
+    }
+
+    #[test]
+    fn searches_become_quoted_match_expressions() {
+        let cases = [
+            ("hades", Some(r#""hades""#)),
+            ("  hollow   knight ", Some(r#""hollow" "knight""#)),
+            (r#""hollow knight""#, Some(r#""hollow knight""#)),
+            ("hol*", Some(r#""hol" *"#)),
+            ("hol**", Some(r#""hol" *"#)),
+            (r#""hollow kn"*"#, Some(r#""hollow kn" *"#)),
+            (r#"say "unterminated"#, Some(r#""say" "unterminated""#)),
+            (r#"a"b"#, Some(r#""a" "b""#)),
+            // Operators, column filters and grouping are taken literally.
+            ("NOT hades", Some(r#""NOT" "hades""#)),
+            ("title:hades", Some(r#""title:hades""#)),
+            ("(celeste OR hades)", Some(r#""(celeste" "OR" "hades)""#)),
+            ("o'neil", Some(r#""o'neil""#)),
+            // Nothing to look for.
+            ("", None),
+            ("   ", None),
+            ("*", None),
+            ("***", None),
+            (r#""""#, None),
+            ("-- ()", None),
+        ];
+
+        for (search, expected) in cases {
+            assert_eq!(
+                GamesProcessor::match_expression(search).as_deref(),
+                expected,
+                "search {:?}",
+                search
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_without_words_are_rejected() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+
+        let response = app
+            .request(
+                Method::GET,
+                "/v1/games/search?q=*",
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -1,7 +1,10 @@
 use axum::{routing::get, Router};
 use tower_http::auth::AsyncRequireAuthorizationLayer;
 
-use crate::{authentication::middleware::JWTAuthorizationLayer, endpoints::Endpoint};
+use crate::{
+    authentication::{middleware::JWTAuthorizationLayer, scope::Scope},
+    endpoints::Endpoint,
+};
 
 use super::{processor::GamesProcessor, GamesEndpoint};
 
@@ -12,6 +15,7 @@ impl Endpoint for GamesEndpoint {
                 "/",
                 get(GamesProcessor::read_all).post(GamesProcessor::create),
             )
+            .route("/search", get(GamesProcessor::search))
             .route(
                 "/:id",
                 get(GamesProcessor::read)
@@ -21,6 +25,8 @@ impl Endpoint for GamesEndpoint {
 
         Router::new()
             .nest("/games", routes)
-            .layer(AsyncRequireAuthorizationLayer::new(JWTAuthorizationLayer))
+            .layer(AsyncRequireAuthorizationLayer::new(
+                JWTAuthorizationLayer::with_scopes(Scope::GamesRead, Scope::GamesWrite),
+            ))
     }
 }
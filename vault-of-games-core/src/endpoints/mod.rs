@@ -1,7 +1,9 @@
 use axum::Router;
 
+pub mod admin;
 pub mod games;
 pub mod users;
+pub mod well_known;
 
 pub trait Endpoint {
     fn connect_router() -> Router;
@@ -2,11 +2,16 @@ pub mod payloads;
 
 use serde::Serialize;
 
-#[derive(Clone, Debug, Serialize)]
+use crate::{authentication::scope::Scopes, endpoints::games::entities::Game};
+
+/// Storage representation of a user, which must never be sent to clients.
+#[derive(Clone, Debug)]
 pub struct User {
     pub id: String,
     pub username: String,
     pub password: String,
+    pub email: Option<String>,
+    pub email_verified_at: Option<String>,
     pub created_at: String,
     pub updated_at: Option<String>,
 }
@@ -16,6 +21,7 @@ impl User {
         id: String,
         username: String,
         password: String,
+        email: Option<String>,
         created_at: String,
         updated_at: Option<String>,
     ) -> Self {
@@ -23,8 +29,123 @@ impl User {
             id,
             username,
             password,
+            email,
+            email_verified_at: None,
             created_at,
             updated_at,
         }
     }
 }
+
+/// Representation of a user that is safe to return from the endpoints.
+#[derive(Clone, Debug, Serialize)]
+pub struct PublicUser {
+    pub id: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
+
+impl From<User> for PublicUser {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified_at.is_some(),
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GamesByStatus {
+    pub untried: i64,
+    pub progressing: i64,
+    pub ended: i64,
+    pub completed: i64,
+    /// Games without a status.
+    pub none: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UserSummary {
+    pub games: i64,
+    /// Distinct categories across all of the user's games.
+    pub categories: i64,
+    pub games_by_status: GamesByStatus,
+}
+
+/// Representation of the bearer's own account.
+#[derive(Clone, Debug, Serialize)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub user: PublicUser,
+    pub summary: UserSummary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScheduledDeletion {
+    pub scheduled_for: String,
+}
+
+/// Everything a user has stored, as handed out by the data export. Notes are part of the games
+/// they were written for.
+#[derive(Clone, Debug, Serialize)]
+pub struct DataExport {
+    pub exported_at: String,
+    pub profile: PublicUser,
+    pub games: Vec<Game>,
+    /// Names of the categories used by any of the games.
+    pub categories: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PersonalAccessToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Scopes,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+/// Newly created personal access token, the only representation that includes its secret.
+#[derive(Clone, Debug, Serialize)]
+pub struct CreatedPersonalAccessToken {
+    #[serde(flatten)]
+    pub personal_access_token: PersonalAccessToken,
+    pub token: String,
+}
+
+/// Signed-in device, which lasts as long as its refresh tokens.
+#[derive(Clone, Debug, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Whether this is the session that the request was made with.
+    pub current: bool,
+    pub created_at: String,
+    pub last_seen_at: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OidcAuthorization {
+    pub authorization_url: String,
+}
+
+/// Secret of a pending two-factor enrollment, which has to be confirmed with a code.
+#[derive(Clone, Debug, Serialize)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
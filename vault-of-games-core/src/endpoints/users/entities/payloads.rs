@@ -1,13 +1,77 @@
 use serde::Deserialize;
 
+use crate::authentication::scope::Scope;
+
 #[derive(Deserialize)]
 pub struct UserAuthenticationPayload {
     pub username: String,
     pub password: String,
+    /// Only read on sign-up.
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
+/// Fields that are left out keep their current values. Passwords are changed through
+/// [`PasswordChangePayload`] instead.
 #[derive(Deserialize)]
 pub struct UserUpdatePayload {
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Changing the email address requires verifying the new one.
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChangePayload {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshTokenPayload {
+    /// Left out by clients that keep their tokens in cookies.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PersonalAccessTokenCreatePayload {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodePayload {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorSignInPayload {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForgotPayload {
     pub username: String,
+}
+
+/// Parameters that the identity provider redirected back to the client with.
+#[derive(Deserialize)]
+pub struct OidcCallbackPayload {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmailVerificationPayload {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetPayload {
+    pub token: String,
     pub password: String,
 }
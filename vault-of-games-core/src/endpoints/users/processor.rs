@@ -1,25 +1,57 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
+    body::{boxed, BoxBody},
+    extract::{Extension, Path, Query},
+    http::{
+        header::{HeaderMap, HeaderValue, CONTENT_DISPOSITION},
+        Response, StatusCode,
+    },
     response::IntoResponse,
     Json,
 };
-use chrono::Utc;
-use jwt_simple::prelude::{Claims, Duration, EdDSAKeyPairLike};
-use rand::Rng;
-use sqlx::{query, query_as, sqlite::SqliteQueryResult};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use sqlx::{query, query_as};
 use uuid::Uuid;
 
 use crate::{
-    authentication::{error::AuthenticationError, keys::LAZY_KEYPAIR, AuthenticationResponse},
+    audit::{AuditAction, AuditEvent, AuditRecord},
+    authentication::{
+        account_deletion, authorize_owner,
+        claims::AuthorizationClaims,
+        cookies::{self, CookieCredentials},
+        email_verification,
+        error::AuthenticationError,
+        lockout::{self, LockoutKey},
+        oidc, password, password_reset,
+        personal_access_tokens::generate_personal_access_token,
+        revocation::{self, REVOCATION_CACHE},
+        scope::Scopes,
+        sessions::{self, ClientMetadata},
+        tokens,
+        two_factor::{self, CHALLENGE_LIFETIME_MINUTES},
+        AuthenticationResponse, SignInResponse, TwoFactorChallengeResponse,
+    },
+    configuration::{Configuration, PasswordConfiguration},
     database::DatabaseConnectionPool,
+    endpoints::games::entities::{Categories, Game, Status},
     error::ProcessorError,
+    mail::Mailer,
+    pagination::{Page, Pagination},
+    validation::ValidationErrors,
 };
 
 use super::entities::{
-    payloads::{UserAuthenticationPayload, UserUpdatePayload},
-    User,
+    payloads::{
+        EmailVerificationPayload, OidcCallbackPayload, PasswordChangePayload,
+        PasswordForgotPayload, PasswordResetPayload, PersonalAccessTokenCreatePayload,
+        RefreshTokenPayload, TwoFactorCodePayload, TwoFactorSignInPayload,
+        UserAuthenticationPayload, UserUpdatePayload,
+    },
+    CreatedPersonalAccessToken, DataExport, GamesByStatus, OidcAuthorization, PersonalAccessToken,
+    Profile, PublicUser, RecoveryCodes, ScheduledDeletion, Session, TwoFactorEnrollment, User,
+    UserSummary,
 };
 
 #[derive(Default)]
@@ -28,6 +60,8 @@ pub struct UsersProcessor;
 impl UsersProcessor {
     pub async fn sign_in(
         Json(payload): Json<UserAuthenticationPayload>,
+        client: ClientMetadata,
+        Extension(configuration): Extension<Arc<Configuration>>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
         if payload.username.is_empty() || payload.password.is_empty() {
@@ -36,79 +70,642 @@ impl UsersProcessor {
             ));
         }
 
+        let now = Utc::now();
+        let username_key = LockoutKey::Username(&payload.username);
+        let address_key = LockoutKey::Address(client.address);
+        let lockout_keys = [
+            LockoutKey::Username(&payload.username),
+            LockoutKey::Address(client.address),
+        ];
+
+        lockout::begin_attempt(&pool, &lockout_keys, now, &configuration.lockout).await?;
+
         let user = query!(
-            "
-            SELECT id, password
+            r#"
+            SELECT id,
+                password,
+                email_verified_at as "email_verified_at?: DateTime<Utc>",
+                totp_enabled_at as "totp_enabled_at?: DateTime<Utc>"
             FROM users
             WHERE username = ?;
-            ",
+            "#,
             payload.username
         )
         .fetch_optional(&pool)
         .await?;
 
-        if let Some(user) = user {
-            if !argon2::verify_encoded(user.password.as_str(), payload.password.as_bytes())? {
+        // Unknown usernames fail the same way as wrong passwords, so that they do not reveal
+        // which usernames exist.
+        let user = match user {
+            Some(user) if password::verify_password(&user.password, &payload.password)? => user,
+            user => {
+                if user.is_none() {
+                    password::verify_dummy_password(&payload.password, &configuration.password)?;
+                }
+
+                let mut record = AuditRecord::new(AuditAction::SignInFailed).with_client(&client);
+
+                if let Some(user_id) = user.as_ref().and_then(|user| user.id.as_deref()) {
+                    record = record.with_target(user_id);
+                }
+
+                record.record(&pool).await;
+
                 return Err(ProcessorError::AuthenticationError(
                     AuthenticationError::WrongCredentials,
                 ));
             }
+        };
+
+        lockout::clear_failures(&pool, &username_key).await?;
+        lockout::forgive_attempt(&pool, &address_key, &configuration.lockout).await?;
+
+        let user_id = user.id.unwrap();
+
+        // Hashes that predate the current argon2 parameters are upgraded while the password is at
+        // hand.
+        if password::needs_rehash(&user.password, &configuration.password) {
+            let password_hash =
+                password::hash_password(&payload.password, &configuration.password)?;
+
+            query!(
+                "
+                UPDATE users
+                SET password = ?1
+                WHERE id = ?2 AND password = ?3;
+                ",
+                password_hash,
+                user_id,
+                user.password,
+            )
+            .execute(&pool)
+            .await?;
+        }
+
+        email_verification::ensure_verified(
+            user.email_verified_at,
+            configuration.email_verification.required_for_sign_in,
+        )?;
+
+        if user.totp_enabled_at.is_some() {
+            return Self::challenge_second_factor(&user_id);
+        }
+
+        let response = Self::complete_sign_in(&pool, &user_id, &client).await?;
+
+        cookies::deliver_tokens(response, &configuration.cookies)
+    }
+
+    /// Answers a sign-in with a challenge that [`Self::sign_in_two_factor`] completes.
+    fn challenge_second_factor(
+        user_id: &str,
+    ) -> Result<(HeaderMap, Json<SignInResponse>), ProcessorError> {
+        Ok((
+            HeaderMap::new(),
+            Json(SignInResponse::TwoFactorRequired(
+                TwoFactorChallengeResponse::new(
+                    two_factor::sign_challenge_token(user_id)?,
+                    CHALLENGE_LIFETIME_MINUTES * 60,
+                ),
+            )),
+        ))
+    }
+
+    /// Completes a sign-in that was answered with a two-factor challenge.
+    pub async fn sign_in_two_factor(
+        Json(payload): Json<TwoFactorSignInPayload>,
+        client: ClientMetadata,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if payload.challenge_token.is_empty() || payload.code.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
+        }
+
+        let user_id = two_factor::verify_challenge_token(&payload.challenge_token)?;
+
+        let user = query!(
+            r#"
+            SELECT username as "username!"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        let now = Utc::now();
+        let username_key = LockoutKey::Username(&user.username);
+        let address_key = LockoutKey::Address(client.address);
+        let lockout_keys = [
+            LockoutKey::Username(&user.username),
+            LockoutKey::Address(client.address),
+        ];
+
+        lockout::begin_attempt(&pool, &lockout_keys, now, &configuration.lockout).await?;
+
+        if !two_factor::verify_second_factor(&pool, &user_id, &payload.code, now).await? {
+            AuditRecord::new(AuditAction::SignInFailed)
+                .with_target(&user_id)
+                .with_client(&client)
+                .record(&pool)
+                .await;
+
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidTwoFactorCode,
+            ));
+        }
+
+        lockout::clear_failures(&pool, &username_key).await?;
+        lockout::forgive_attempt(&pool, &address_key, &configuration.lockout).await?;
+
+        let response = Self::complete_sign_in(&pool, &user_id, &client).await?;
+
+        cookies::deliver_tokens(response, &configuration.cookies)
+    }
+
+    /// Starts signing in with an external identity provider, returning the URL to send the user
+    /// to.
+    pub async fn authorize_with_provider(
+        Path(provider): Path<String>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let provider = configuration
+            .oidc
+            .find(&provider)
+            .ok_or(AuthenticationError::UnknownProvider)?;
+
+        let authorization_url = oidc::begin_authorization(&pool, provider).await?;
+
+        Ok(Json(OidcAuthorization { authorization_url }))
+    }
+
+    /// Completes signing in with an external identity provider. Accounts with two-factor
+    /// authentication enabled still have to pass the challenge.
+    pub async fn sign_in_with_provider(
+        Path(provider): Path<String>,
+        Json(payload): Json<OidcCallbackPayload>,
+        client: ClientMetadata,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let provider = configuration
+            .oidc
+            .find(&provider)
+            .ok_or(AuthenticationError::UnknownProvider)?;
+
+        if payload.code.is_empty() || payload.state.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
+        }
+
+        let identity =
+            oidc::complete_authorization(&pool, provider, &payload.code, &payload.state).await?;
+        let user_id =
+            oidc::resolve_user(&pool, provider, &identity, &configuration.password).await?;
+
+        let user = query!(
+            r#"
+            SELECT email_verified_at as "email_verified_at?: DateTime<Utc>",
+                totp_enabled_at as "totp_enabled_at?: DateTime<Utc>"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        email_verification::ensure_verified(
+            user.email_verified_at,
+            configuration.email_verification.required_for_sign_in,
+        )?;
+
+        if user.totp_enabled_at.is_some() {
+            return Self::challenge_second_factor(&user_id);
+        }
+
+        let response = Self::complete_sign_in(&pool, &user_id, &client).await?;
+
+        cookies::deliver_tokens(response, &configuration.cookies)
+    }
+
+    /// Issues tokens once the user has proven who they are, keeping the account if it was
+    /// scheduled for deletion.
+    async fn complete_sign_in(
+        pool: &DatabaseConnectionPool,
+        user_id: &str,
+        client: &ClientMetadata,
+    ) -> Result<AuthenticationResponse, ProcessorError> {
+        if account_deletion::cancel_deletion(pool, user_id).await? {
+            Self::record_account_event(
+                pool,
+                AuditAction::AccountDeletionCancelled,
+                user_id,
+                client,
+            )
+            .await;
+        }
+
+        let response = tokens::issue_tokens(pool, user_id, client).await?;
+
+        Self::record_account_event(pool, AuditAction::SignIn, user_id, client).await;
+
+        Ok(response)
+    }
+
+    /// Rotates the refresh token from the payload or, for clients that keep their tokens in
+    /// cookies, from the refresh token cookie.
+    pub async fn refresh_token(
+        Json(payload): Json<RefreshTokenPayload>,
+        credentials: CookieCredentials,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let transport = configuration.cookies.transport;
+
+        let refresh_token = payload
+            .refresh_token
+            .filter(|refresh_token| !refresh_token.is_empty() && transport.accepts_header())
+            .or_else(|| {
+                credentials
+                    .refresh_token
+                    .clone()
+                    .filter(|_| transport.accepts_cookie() && credentials.csrf_token_matches())
+            })
+            .ok_or(AuthenticationError::MissingCredentials)?;
+
+        let response = tokens::rotate_refresh_token(&pool, &refresh_token).await?;
+
+        cookies::deliver_tokens(response, &configuration.cookies)
+    }
+
+    pub async fn sign_out(
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        revocation::revoke_token(&pool, &claims).await?;
+        tokens::revoke_token_family(
+            &pool,
+            claims.subject.as_deref().unwrap(),
+            &claims.custom.session_id,
+        )
+        .await?;
+
+        AuditRecord::new(AuditAction::SignOut)
+            .with_actor(claims.subject.as_deref().unwrap())
+            .with_target(&claims.custom.session_id)
+            .with_client(&client)
+            .record(&pool)
+            .await;
+
+        Ok((
+            cookies::clear_session_cookies(&configuration.cookies)?,
+            StatusCode::NO_CONTENT,
+        ))
+    }
+
+    pub async fn read_sessions(
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+        let now = Utc::now();
+
+        // Recent activity may still be buffered, so it is written out before reading.
+        sessions::flush_last_seen(&pool).await?;
+
+        let sessions = query_as!(
+            Session,
+            r#"
+            SELECT s.id as "id!",
+                s.user_agent as "user_agent?",
+                s.ip_address as "ip_address?",
+                s.id = ?1 as "current!: bool",
+                s.created_at as "created_at!: String",
+                s.last_seen_at as "last_seen_at?: String"
+            FROM sessions s
+            WHERE s.user_id = ?2
+              AND s.revoked_at IS NULL
+              AND EXISTS(SELECT 1
+                         FROM refresh_tokens rt
+                         WHERE rt.family_id = s.id
+                           AND rt.rotated_at IS NULL
+                           AND rt.revoked_at IS NULL
+                           AND rt.expires_at > ?3)
+            ORDER BY s.last_seen_at DESC;
+            "#,
+            claims.custom.session_id,
+            user_id,
+            now,
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(Json(sessions))
+    }
+
+    pub async fn delete_session(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        sessions::revoke_session(&pool, &user_id, &id).await?;
+
+        AuditRecord::new(AuditAction::SessionRevoked)
+            .with_actor(&user_id)
+            .with_target(&id)
+            .with_client(&client)
+            .record(&pool)
+            .await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn sign_out_everywhere(
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
 
-            let claims = Claims::create(Duration::from_mins(15)).with_subject(user.id.unwrap());
+        revocation::revoke_all_tokens(&pool, &user_id).await?;
 
-            let token = LAZY_KEYPAIR.private_key.sign(claims)?;
+        Self::record_account_event(&pool, AuditAction::SignOutEverywhere, &user_id, &client).await;
 
-            Ok(Json(AuthenticationResponse::new(token)))
-        } else {
-            Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound))
+        Ok((
+            cookies::clear_session_cookies(&configuration.cookies)?,
+            StatusCode::NO_CONTENT,
+        ))
+    }
+
+    /// Mails a password reset link to the user. The response is the same whether the user exists
+    /// or not, so that it cannot be used to probe for accounts.
+    pub async fn forgot_password(
+        Json(payload): Json<PasswordForgotPayload>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(mailer): Extension<Arc<dyn Mailer>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if payload.username.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
+        }
+
+        let user = query!(
+            r#"
+            SELECT id as "id!",
+                email as "email?",
+                email_verified_at as "email_verified_at?: DateTime<Utc>"
+            FROM users
+            WHERE username = ?;
+            "#,
+            payload.username
+        )
+        .fetch_optional(&pool)
+        .await?;
+
+        // Accounts without an address that reset links may be sent to are skipped just as
+        // silently as unknown usernames.
+        let recipient = user.and_then(|user| {
+            let verified = user.email_verified_at.is_some()
+                || !configuration.email_verification.required_for_password_reset;
+
+            user.email
+                .filter(|_| verified)
+                .map(|email| (user.id, email))
+        });
+
+        // Issuing the token and sending the mail take time that unknown usernames would not, so
+        // they happen after the response.
+        if let Some((user_id, email)) = recipient {
+            tokio::spawn(async move {
+                if let Err(error) = password_reset::send_reset_mail(
+                    &pool,
+                    mailer.as_ref(),
+                    &configuration,
+                    &user_id,
+                    &email,
+                )
+                .await
+                {
+                    tracing::error!("Failed to issue password reset token: {}", error);
+                }
+            });
+        }
+
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    /// Sets a new password with a reset token, signing the user out everywhere.
+    pub async fn reset_password(
+        Json(payload): Json<PasswordResetPayload>,
+        client: ClientMetadata,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if payload.token.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
         }
+
+        // The password is validated before redeeming the token, so that a rejected password does
+        // not use it up.
+        let username = password_reset::reset_token_owner(&pool, &payload.token).await?;
+        let mut errors = ValidationErrors::new();
+
+        password::validate_password(
+            &payload.password,
+            &username,
+            &configuration.password,
+            &mut errors,
+        );
+
+        errors.into_result()?;
+
+        let password_hash = password::hash_password(&payload.password, &configuration.password)?;
+
+        // The token is only used up along with the password change, and neither happens without
+        // the other sessions being signed out.
+        let mut transaction = pool.begin().await?;
+
+        let user_id = password_reset::redeem_reset_token(&mut transaction, &payload.token).await?;
+
+        query!(
+            "
+            UPDATE users
+            SET password   = ?1,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+            password_hash,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        revocation::revoke_all_tokens_within(&mut transaction, &user_id).await?;
+
+        transaction.commit().await?;
+
+        REVOCATION_CACHE.forget_user(&user_id);
+
+        Self::record_account_event(&pool, AuditAction::PasswordReset, &user_id, &client).await;
+
+        Ok(StatusCode::NO_CONTENT)
     }
 
     pub async fn sign_up(
         Json(payload): Json<UserAuthenticationPayload>,
+        client: ClientMetadata,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(mailer): Extension<Arc<dyn Mailer>>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
-        let password_hash = argon2::hash_encoded(
-            payload.password.as_bytes(),
-            &rand::thread_rng().gen::<[u8; 32]>(),
-            &argon2::Config::default(),
+        let email = Self::validate_credentials(
+            &payload.username,
+            &payload.password,
+            payload.email.as_deref(),
+            &configuration.password,
         )?;
 
+        Self::ensure_username_available(&pool, &payload.username, None).await?;
+
+        if let Some(email) = &email {
+            email_verification::ensure_email_available(&pool, email, None).await?;
+        }
+
+        let password_hash = password::hash_password(&payload.password, &configuration.password)?;
+
         let user = User::new(
             Uuid::new_v4().to_string(),
             payload.username,
             password_hash,
+            email,
             Utc::now().to_string(),
             None,
         );
 
         query!(
             "
-            INSERT INTO users (id, username, password, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5);
+            INSERT INTO users (id, username, password, email, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6);
             ",
             user.id,
             user.username,
             user.password,
+            user.email,
             user.created_at,
             user.updated_at,
         )
         .execute(&pool)
         .await?;
 
-        Ok((StatusCode::CREATED, Json(user)))
+        Self::record_account_event(&pool, AuditAction::SignUp, &user.id, &client).await;
+
+        if let Some(email) = &user.email {
+            email_verification::send_verification_mail(
+                &pool,
+                mailer.as_ref(),
+                &configuration,
+                &user.id,
+                email,
+            )
+            .await?;
+        }
+
+        Ok((StatusCode::CREATED, Json(PublicUser::from(user))))
     }
 
     pub async fn read(
         Path(id): Path<String>,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        authorize_owner(&claims, &id)?;
+
+        let user = Self::read_user(&pool, &id).await?;
+
+        Ok(Json(user))
+    }
+
+    /// Profile of the bearer along with a summary of their collection.
+    pub async fn read_me(
+        Extension(claims): Extension<AuthorizationClaims>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let user = Self::read_user(&pool, &user_id).await?;
+
+        let games = query!(
+            r#"
+            SELECT COUNT(*) as "games!: i64",
+                COUNT(CASE WHEN status = 'untried' THEN 1 END) as "untried!: i64",
+                COUNT(CASE WHEN status = 'progressing' THEN 1 END) as "progressing!: i64",
+                COUNT(CASE WHEN status = 'ended' THEN 1 END) as "ended!: i64",
+                COUNT(CASE WHEN status = 'completed' THEN 1 END) as "completed!: i64",
+                COUNT(CASE WHEN status IS NULL THEN 1 END) as "none!: i64"
+            FROM games
+            WHERE user_id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        let categories = query!(
+            r#"
+            SELECT COUNT(DISTINCT gc.category_id) as "categories!: i64"
+            FROM games_categories gc
+                INNER JOIN games g ON g.id = gc.game_id
+            WHERE g.user_id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(Json(Profile {
+            user,
+            summary: UserSummary {
+                games: games.games,
+                categories: categories.categories,
+                games_by_status: GamesByStatus {
+                    untried: games.untried,
+                    progressing: games.progressing,
+                    ended: games.ended,
+                    completed: games.completed,
+                    none: games.none,
+                },
+            },
+        }))
+    }
+
+    async fn read_user(
+        pool: &DatabaseConnectionPool,
+        id: &str,
+    ) -> Result<PublicUser, ProcessorError> {
         let user = query_as!(
-            User,
+            PublicUser,
             r#"
             SELECT id as "id!",
                 username as "username!",
-                password as "password!",
+                email as "email?",
+                email_verified_at IS NOT NULL as "email_verified!: bool",
                 created_at as "created_at!: String",
                 updated_at as "updated_at?: String"
             FROM users
@@ -116,68 +713,1880 @@ impl UsersProcessor {
             "#,
             id
         )
-        .fetch_one(&pool)
+        .fetch_one(pool)
         .await?;
 
         if user.id.is_empty() {
             return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
         }
 
-        Ok(Json(user))
+        Ok(user)
     }
 
     pub async fn update(
         Path(id): Path<String>,
         Json(payload): Json<UserUpdatePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(mailer): Extension<Arc<dyn Mailer>>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
-        let password_hash = argon2::hash_encoded(
-            payload.password.as_bytes(),
-            &rand::thread_rng().gen::<[u8; 32]>(),
-            &argon2::Config::default(),
-        )?;
+        authorize_owner(&claims, &id)?;
+
+        Self::update_user(&pool, &configuration, mailer.as_ref(), &id, payload).await?;
+        Self::record_account_event(&pool, AuditAction::AccountUpdated, &id, &client).await;
+
+        Ok(())
+    }
+
+    pub async fn update_me(
+        Json(payload): Json<UserUpdatePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(mailer): Extension<Arc<dyn Mailer>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        Self::update_user(&pool, &configuration, mailer.as_ref(), &user_id, payload).await?;
+        Self::record_account_event(&pool, AuditAction::AccountUpdated, &user_id, &client).await;
+
+        Ok(())
+    }
+
+    async fn update_user(
+        pool: &DatabaseConnectionPool,
+        configuration: &Configuration,
+        mailer: &dyn Mailer,
+        id: &str,
+        payload: UserUpdatePayload,
+    ) -> Result<(), ProcessorError> {
+        let mut errors = ValidationErrors::new();
+
+        if let Some(username) = &payload.username {
+            Self::validate_username(username, &mut errors);
+        }
+
+        let email = payload
+            .email
+            .as_deref()
+            .and_then(|email| email_verification::normalize_email(email, &mut errors));
+
+        errors.into_result()?;
+
+        if let Some(username) = &payload.username {
+            Self::ensure_username_available(pool, username, Some(id)).await?;
+        }
+
+        if let Some(email) = &email {
+            email_verification::ensure_email_available(pool, email, Some(id)).await?;
+        }
 
         let user = query!(
             "
             UPDATE users
-            SET username   = ?1,
-                password   = ?2,
+            SET username   = COALESCE(?1, username),
                 updated_at = CURRENT_TIMESTAMP
-            WHERE id = ?3;
+            WHERE id = ?2;
             ",
             payload.username,
-            password_hash,
             id,
         )
-        .execute(&pool)
+        .execute(pool)
         .await?;
 
         if user.rows_affected() == 0 {
             return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
         }
 
+        if let Some(email) = email {
+            // The verification of the previous address does not carry over to a new one.
+            let change = query!(
+                "
+                UPDATE users
+                SET email             = ?1,
+                    email_verified_at = NULL
+                WHERE id = ?2 AND (email IS NULL OR email != ?1);
+                ",
+                email,
+                id,
+            )
+            .execute(pool)
+            .await?;
+
+            if change.rows_affected() > 0 {
+                email_verification::send_verification_mail(pool, mailer, configuration, id, &email)
+                    .await?;
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn delete(
-        Path(id): Path<String>,
+    /// Changes the password of the bearer, signing out every other session. The current session
+    /// continues with the returned tokens.
+    pub async fn change_password(
+        Json(payload): Json<PasswordChangePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
         Extension(pool): Extension<DatabaseConnectionPool>,
     ) -> Result<impl IntoResponse, ProcessorError> {
-        let user: SqliteQueryResult = query!(
-            "
-            DELETE
+        let user_id = claims.subject.unwrap();
+
+        let user = query!(
+            r#"
+            SELECT username as "username!", password as "password!"
             FROM users
-            where id = ?;
-            ",
-            id
+            WHERE id = ?;
+            "#,
+            user_id
         )
-        .execute(&pool)
+        .fetch_one(&pool)
         .await?;
 
-        if user.rows_affected() == 0 {
+        let now = Utc::now();
+        let username_key = LockoutKey::Username(&user.username);
+
+        lockout::begin_attempt(
+            &pool,
+            std::slice::from_ref(&username_key),
+            now,
+            &configuration.lockout,
+        )
+        .await?;
+
+        if !password::verify_password(&user.password, &payload.current_password)? {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::WrongCredentials,
+            ));
+        }
+
+        lockout::clear_failures(&pool, &username_key).await?;
+
+        let mut errors = ValidationErrors::new();
+
+        password::validate_password(
+            &payload.new_password,
+            &user.username,
+            &configuration.password,
+            &mut errors,
+        );
+
+        errors.into_result()?;
+
+        let password_hash =
+            password::hash_password(&payload.new_password, &configuration.password)?;
+
+        query!(
+            "
+            UPDATE users
+            SET password   = ?1,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+            password_hash,
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        revocation::revoke_all_tokens(&pool, &user_id).await?;
+
+        Self::record_account_event(&pool, AuditAction::PasswordChanged, &user_id, &client).await;
+
+        let response = tokens::issue_tokens(&pool, &user_id, &client).await?;
+
+        cookies::deliver_tokens(response, &configuration.cookies)
+    }
+
+    pub async fn verify_email(
+        Json(payload): Json<EmailVerificationPayload>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if payload.token.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
+        }
+
+        email_verification::verify_email(&pool, &payload.token).await?;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    pub async fn resend_email_verification(
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(mailer): Extension<Arc<dyn Mailer>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let user = query!(
+            r#"
+            SELECT email as "email?",
+                email_verified_at as "email_verified_at?: DateTime<Utc>"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        let email = user.email.ok_or(AuthenticationError::MissingEmail)?;
+
+        if user.email_verified_at.is_none() {
+            email_verification::send_verification_mail(
+                &pool,
+                mailer.as_ref(),
+                &configuration,
+                &user_id,
+                &email,
+            )
+            .await?;
+        }
+
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    pub async fn delete(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        authorize_owner(&claims, &id)?;
+
+        Self::delete_user(&pool, &configuration, &id, &client).await
+    }
+
+    pub async fn delete_me(
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(configuration): Extension<Arc<Configuration>>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        Self::delete_user(&pool, &configuration, &user_id, &client).await
+    }
+
+    /// Schedules the account for deletion after the grace period, or deletes it right away
+    /// without one.
+    async fn delete_user(
+        pool: &DatabaseConnectionPool,
+        configuration: &Configuration,
+        id: &str,
+        client: &ClientMetadata,
+    ) -> Result<Response<BoxBody>, ProcessorError> {
+        if configuration.account_deletion.grace_period_days == 0 {
+            account_deletion::delete_account(pool, id).await?;
+
+            Self::record_account_event(pool, AuditAction::AccountDeleted, id, client).await;
+
+            return Ok(StatusCode::NO_CONTENT.into_response().map(boxed));
+        }
+
+        let scheduled_for =
+            account_deletion::schedule_deletion(pool, id, &configuration.account_deletion).await?;
+
+        Self::record_account_event(pool, AuditAction::AccountDeletionScheduled, id, client).await;
+
+        Ok((
+            StatusCode::ACCEPTED,
+            Json(ScheduledDeletion {
+                scheduled_for: scheduled_for.to_string(),
+            }),
+        )
+            .into_response()
+            .map(boxed))
+    }
+
+    /// Archive of everything the bearer has stored, which they can download at any time.
+    pub async fn export(
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let profile = Self::read_user(&pool, &user_id).await?;
+
+        let games = query_as!(
+            Game,
+            r#"
+            SELECT g.id as "id!",
+                g.user_id as "user_id!",
+                g.title as "title!",
+                g.image_url as "image_url?",
+                g.status as "status?: Status",
+                g.rating as "rating?: u8",
+                json_group_array(c.name) FILTER (WHERE c.name IS NOT NULL) as "categories?: Categories",
+                g.note as "note?",
+                g.created_at as "created_at!: String",
+                g.updated_at as "updated_at?: String",
+                g.version as "version!: i64"
+            FROM games g
+                    LEFT JOIN games_categories gc on g.id = gc.game_id
+                    LEFT JOIN categories c on c.id = gc.category_id
+            WHERE g.user_id = ?
+            GROUP BY g.id, g.title, g.image_url, g.status, g.rating, g.note, g.created_at, g.updated_at, g.version
+            ORDER BY g.created_at;
+            "#,
+            user_id,
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        let categories = query!(
+            r#"
+            SELECT DISTINCT c.name as "name!"
+            FROM categories c
+                    INNER JOIN games_categories gc on c.id = gc.category_id
+                    INNER JOIN games g on g.id = gc.game_id
+            WHERE g.user_id = ?
+            ORDER BY c.name;
+            "#,
+            user_id
+        )
+        .fetch_all(&pool)
+        .await?
+        .into_iter()
+        .map(|category| category.name)
+        .collect();
+
+        Self::record_account_event(&pool, AuditAction::DataExported, &user_id, &client).await;
+
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            CONTENT_DISPOSITION,
+            HeaderValue::from_static("attachment; filename=\"vault-of-games-export.json\""),
+        );
+
+        Ok((
+            headers,
+            Json(DataExport {
+                exported_at: Utc::now().to_string(),
+                profile,
+                games,
+                categories,
+            }),
+        ))
+    }
+
+    pub async fn create_personal_access_token(
+        Json(payload): Json<PersonalAccessTokenCreatePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        if payload.name.is_empty() || payload.scopes.is_empty() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::MissingCredentials,
+            ));
+        }
+
+        let user_id = claims.subject.unwrap();
+        let token = generate_personal_access_token()?;
+        let token_hash = tokens::hash_opaque_token(&token)?;
+        let scopes = Scopes::new(payload.scopes);
+        let serialized_scopes = serde_json::to_string(&scopes.content)
+            .map_err(|_| AuthenticationError::TokenCreation)?;
+        let expires_at = payload
+            .expires_in_days
+            .map(|days| Utc::now() + ChronoDuration::days(days.into()));
+
+        let personal_access_token = PersonalAccessToken {
+            id: Uuid::new_v4().to_string(),
+            name: payload.name,
+            scopes,
+            expires_at: expires_at.map(|expires_at| expires_at.to_string()),
+            last_used_at: None,
+            created_at: Utc::now().to_string(),
+        };
+
+        query!(
+            "
+            INSERT INTO personal_access_tokens (id, user_id, name, token_hash, scopes, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);
+            ",
+            personal_access_token.id,
+            user_id,
+            personal_access_token.name,
+            token_hash,
+            serialized_scopes,
+            expires_at,
+            personal_access_token.created_at,
+        )
+        .execute(&pool)
+        .await?;
+
+        AuditRecord::new(AuditAction::TokenCreated)
+            .with_actor(&user_id)
+            .with_target(&personal_access_token.id)
+            .with_client(&client)
+            .record(&pool)
+            .await;
+
+        Ok((
+            StatusCode::CREATED,
+            Json(CreatedPersonalAccessToken {
+                personal_access_token,
+                token,
+            }),
+        ))
+    }
+
+    pub async fn read_personal_access_tokens(
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let personal_access_tokens = query_as!(
+            PersonalAccessToken,
+            r#"
+            SELECT id as "id!",
+                name as "name!",
+                scopes as "scopes!: Scopes",
+                expires_at as "expires_at?: String",
+                last_used_at as "last_used_at?: String",
+                created_at as "created_at!: String"
+            FROM personal_access_tokens
+            WHERE user_id = ? AND revoked_at IS NULL
+            ORDER BY created_at DESC;
+            "#,
+            user_id
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(Json(personal_access_tokens))
+    }
+
+    pub async fn delete_personal_access_token(
+        Path(id): Path<String>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+        let now = Utc::now();
+
+        let personal_access_token = query!(
+            "
+            UPDATE personal_access_tokens
+            SET revoked_at = ?1,
+                updated_at = ?1
+            WHERE id = ?2 AND user_id = ?3 AND revoked_at IS NULL;
+            ",
+            now,
+            id,
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        if personal_access_token.rows_affected() == 0 {
             return Err(ProcessorError::DatabaseError(sqlx::Error::RowNotFound));
         }
 
+        AuditRecord::new(AuditAction::TokenRevoked)
+            .with_actor(&user_id)
+            .with_target(&id)
+            .with_client(&client)
+            .record(&pool)
+            .await;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Starts enrolling the user in two-factor authentication, which only takes effect once a code
+    /// is confirmed.
+    pub async fn enroll_two_factor(
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        let user = query!(
+            r#"
+            SELECT username as "username!",
+                totp_enabled_at as "totp_enabled_at?: DateTime<Utc>"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        if user.totp_enabled_at.is_some() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::TwoFactorAlreadyEnabled,
+            ));
+        }
+
+        let secret = two_factor::generate_secret();
+
+        query!(
+            "
+            UPDATE users
+            SET totp_secret         = ?1,
+                totp_last_used_step = NULL,
+                updated_at          = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+            secret,
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        Ok(Json(TwoFactorEnrollment {
+            provisioning_uri: two_factor::provisioning_uri(&secret, &user.username),
+            secret,
+        }))
+    }
+
+    pub async fn confirm_two_factor(
+        Json(payload): Json<TwoFactorCodePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+        let now = Utc::now();
+
+        let user = query!(
+            r#"
+            SELECT totp_secret as "totp_secret?: String",
+                totp_enabled_at as "totp_enabled_at?: DateTime<Utc>",
+                totp_last_used_step as "totp_last_used_step?: i64"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        if user.totp_enabled_at.is_some() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::TwoFactorAlreadyEnabled,
+            ));
+        }
+
+        let secret = user
+            .totp_secret
+            .ok_or(AuthenticationError::TwoFactorNotEnrolled)?;
+        let step = two_factor::verify_code(
+            &secret,
+            &payload.code,
+            now.timestamp() as u64,
+            user.totp_last_used_step,
+        )
+        .ok_or(AuthenticationError::InvalidTwoFactorCode)? as i64;
+
+        query!(
+            "
+            UPDATE users
+            SET totp_enabled_at     = ?1,
+                totp_last_used_step = ?2,
+                updated_at          = ?1
+            WHERE id = ?3;
+            ",
+            now,
+            step,
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        let recovery_codes = two_factor::replace_recovery_codes(&pool, &user_id).await?;
+
+        Self::record_account_event(&pool, AuditAction::TwoFactorEnabled, &user_id, &client).await;
+
+        Ok(Json(RecoveryCodes { recovery_codes }))
+    }
+
+    pub async fn regenerate_recovery_codes(
+        Json(payload): Json<TwoFactorCodePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        Self::require_second_factor(&pool, &user_id, &payload.code).await?;
+
+        let recovery_codes = two_factor::replace_recovery_codes(&pool, &user_id).await?;
+
+        Self::record_account_event(
+            &pool,
+            AuditAction::RecoveryCodesRegenerated,
+            &user_id,
+            &client,
+        )
+        .await;
+
+        Ok(Json(RecoveryCodes { recovery_codes }))
+    }
+
+    pub async fn disable_two_factor(
+        Json(payload): Json<TwoFactorCodePayload>,
+        client: ClientMetadata,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+
+        Self::require_second_factor(&pool, &user_id, &payload.code).await?;
+
+        query!(
+            "
+            UPDATE users
+            SET totp_secret         = NULL,
+                totp_enabled_at     = NULL,
+                totp_last_used_step = NULL,
+                updated_at          = CURRENT_TIMESTAMP
+            WHERE id = ?;
+            ",
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        query!(
+            "
+            DELETE
+            FROM recovery_codes
+            WHERE user_id = ?;
+            ",
+            user_id,
+        )
+        .execute(&pool)
+        .await?;
+
+        Self::record_account_event(&pool, AuditAction::TwoFactorDisabled, &user_id, &client).await;
+
         Ok(StatusCode::NO_CONTENT)
     }
+
+    /// Events that were performed by the bearer or that concern their account, newest first.
+    pub async fn read_audit_events(
+        Query(pagination): Query<Pagination>,
+        Extension(claims): Extension<AuthorizationClaims>,
+        Extension(pool): Extension<DatabaseConnectionPool>,
+    ) -> Result<impl IntoResponse, ProcessorError> {
+        let user_id = claims.subject.unwrap();
+        let limit = pagination.limit();
+        let offset = pagination.offset();
+
+        let events = query_as!(
+            AuditEvent,
+            r#"
+            SELECT id as "id!",
+                actor_id as "actor_id?",
+                action as "action!: AuditAction",
+                target_id as "target_id?",
+                ip_address as "ip_address?",
+                user_agent as "user_agent?",
+                created_at as "created_at!: String"
+            FROM audit_events
+            WHERE actor_id = ?1 OR target_id = ?1
+            ORDER BY created_at DESC
+            LIMIT ?2 OFFSET ?3;
+            "#,
+            user_id,
+            limit,
+            offset,
+        )
+        .fetch_all(&pool)
+        .await?;
+
+        let total = query!(
+            r#"
+            SELECT COUNT(*) as "total!: i64"
+            FROM audit_events
+            WHERE actor_id = ?1 OR target_id = ?1;
+            "#,
+            user_id
+        )
+        .fetch_one(&pool)
+        .await?
+        .total;
+
+        Ok(Json(Page::new(events, &pagination, total)))
+    }
+
+    /// Records an action that the user performed on their own account.
+    async fn record_account_event(
+        pool: &DatabaseConnectionPool,
+        action: AuditAction,
+        user_id: &str,
+        client: &ClientMetadata,
+    ) {
+        AuditRecord::new(action)
+            .with_actor(user_id)
+            .with_target(user_id)
+            .with_client(client)
+            .record(pool)
+            .await;
+    }
+
+    /// Changes to an enabled second factor have to be confirmed with a code.
+    async fn require_second_factor(
+        pool: &DatabaseConnectionPool,
+        user_id: &str,
+        code: &str,
+    ) -> Result<(), ProcessorError> {
+        let user = query!(
+            r#"
+            SELECT totp_enabled_at as "totp_enabled_at?: DateTime<Utc>"
+            FROM users
+            WHERE id = ?;
+            "#,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        if user.totp_enabled_at.is_none() {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::TwoFactorNotEnrolled,
+            ));
+        }
+
+        if !two_factor::verify_second_factor(pool, user_id, code, Utc::now()).await? {
+            return Err(ProcessorError::AuthenticationError(
+                AuthenticationError::InvalidTwoFactorCode,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Validates a sign-up, returning the normalized email address.
+    fn validate_credentials(
+        username: &str,
+        password: &str,
+        email: Option<&str>,
+        configuration: &PasswordConfiguration,
+    ) -> Result<Option<String>, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        Self::validate_username(username, &mut errors);
+        password::validate_password(password, username, configuration, &mut errors);
+
+        let email = email.and_then(|email| email_verification::normalize_email(email, &mut errors));
+
+        errors.into_result()?;
+
+        Ok(email)
+    }
+
+    fn validate_username(username: &str, errors: &mut ValidationErrors) {
+        if username.trim().is_empty() {
+            errors.add("username", "must not be empty");
+        }
+    }
+
+    /// Rejects usernames that belong to any account but the one identified by `user_id`.
+    async fn ensure_username_available(
+        pool: &DatabaseConnectionPool,
+        username: &str,
+        user_id: Option<&str>,
+    ) -> Result<(), ProcessorError> {
+        let record = query!(
+            r#"
+            SELECT id as "id!"
+            FROM users
+            WHERE username = ?;
+            "#,
+            username
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        match record {
+            Some(record) if Some(record.id.as_str()) != user_id => {
+                Err(AuthenticationError::UsernameTaken.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Utc;
+    use hyper::{
+        header::{CONTENT_TYPE, COOKIE, SET_COOKIE},
+        Body, Method, Request, StatusCode,
+    };
+    use serde_json::json;
+
+    use crate::{
+        authentication::{
+            account_deletion,
+            cookies::{
+                ACCESS_TOKEN_COOKIE, CSRF_TOKEN_COOKIE, CSRF_TOKEN_HEADER, REFRESH_TOKEN_COOKIE,
+                REFRESH_TOKEN_COOKIE_PATH,
+            },
+            password, password_reset, two_factor,
+        },
+        configuration::{Configuration, CookieConfiguration, OidcConfiguration, TokenTransport},
+        testing::{self, MockAuthorization, MockIdentityProvider, TestApp, TestResponse, PASSWORD},
+    };
+
+    #[tokio::test]
+    async fn users_cannot_access_the_accounts_of_others() {
+        let app = TestApp::new().await;
+        let (alice, alice_token) = app.user("alice").await;
+        let (_, bob_token) = app.user("bob").await;
+        let uri = format!("/v1/users/{}", alice);
+
+        let read = app.request(Method::GET, &uri, Some(&bob_token), None).await;
+        let update = app
+            .request(
+                Method::PATCH,
+                &uri,
+                Some(&bob_token),
+                Some(json!({ "username": "mallory" })),
+            )
+            .await;
+        let delete = app
+            .request(Method::DELETE, &uri, Some(&bob_token), None)
+            .await;
+
+        assert_eq!(read.status, StatusCode::FORBIDDEN);
+        assert_eq!(update.status, StatusCode::FORBIDDEN);
+        assert_eq!(delete.status, StatusCode::FORBIDDEN);
+
+        let own = app
+            .request(Method::GET, &uri, Some(&alice_token), None)
+            .await;
+
+        assert_eq!(own.status, StatusCode::OK);
+        assert_eq!(own.body["username"], "alice");
+    }
+
+    #[tokio::test]
+    async fn responses_never_contain_the_password_hash() {
+        let app = TestApp::new().await;
+        let sign_up = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-up",
+                None,
+                Some(json!({ "username": "alice", "password": PASSWORD })),
+            )
+            .await;
+        let alice = sign_up.body["id"].as_str().unwrap().to_string();
+        let token = app.sign_in("alice").await.body["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let responses = [
+            sign_up,
+            app.request(
+                Method::GET,
+                &format!("/v1/users/{}", alice),
+                Some(&token),
+                None,
+            )
+            .await,
+            app.request(Method::GET, "/v1/users/me", Some(&token), None)
+                .await,
+            app.request(Method::GET, "/v1/users/me/export", Some(&token), None)
+                .await,
+        ];
+
+        for response in responses {
+            assert!(response.status.is_success());
+
+            let body = response.body.to_string();
+
+            assert!(!body.contains("password"), "{}", body);
+            assert!(!body.contains("$argon2"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_usernames_fail_like_wrong_passwords() {
+        let app = TestApp::new().await;
+
+        app.sign_up("alice").await;
+
+        let wrong_password = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-in",
+                None,
+                Some(json!({ "username": "alice", "password": "not the password" })),
+            )
+            .await;
+        let unknown_username = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-in",
+                None,
+                Some(json!({ "username": "nobody", "password": "not the password" })),
+            )
+            .await;
+
+        assert_eq!(wrong_password.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(unknown_username.status, wrong_password.status);
+        assert_eq!(unknown_username.body, wrong_password.body);
+    }
+
+    async fn forgot_password(app: &TestApp, username: &str) -> StatusCode {
+        app.request(
+            Method::POST,
+            "/v1/users/password/forgot",
+            None,
+            Some(json!({ "username": username })),
+        )
+        .await
+        .status
+    }
+
+    #[tokio::test]
+    async fn forgot_password_mails_a_working_reset_link() {
+        let app = TestApp::new().await;
+        let user_id = app.sign_up("alice").await;
+
+        sqlx::query(
+            "UPDATE users SET email = 'alice@example.com', email_verified_at = CURRENT_TIMESTAMP WHERE id = ?;",
+        )
+        .bind(&user_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(forgot_password(&app, "alice").await, StatusCode::ACCEPTED);
+
+        let mail = app.mailer.wait_for(1).await;
+
+        assert_eq!(mail.len(), 1);
+        assert_eq!(mail[0].to, "alice@example.com");
+
+        let token = mail[0]
+            .body
+            .split("token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap();
+
+        let reset = app
+            .request(
+                Method::POST,
+                "/v1/users/password/reset",
+                None,
+                Some(json!({ "token": token, "password": "a brand new passphrase" })),
+            )
+            .await;
+
+        assert_eq!(reset.status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn forgot_password_answers_the_same_without_mailing_unknown_users() {
+        let app = TestApp::new().await;
+        app.sign_up("alice").await;
+
+        // Neither an unknown username nor an account without a verified address gets mail.
+        assert_eq!(forgot_password(&app, "bob").await, StatusCode::ACCEPTED);
+        assert_eq!(forgot_password(&app, "alice").await, StatusCode::ACCEPTED);
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert!(app.mailer.sent().is_empty());
+    }
+
+    async fn reset_password(app: &TestApp, token: &str, password: &str) -> TestResponse {
+        app.request(
+            Method::POST,
+            "/v1/users/password/reset",
+            None,
+            Some(json!({ "token": token, "password": password })),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn stale_reset_tokens_do_not_reveal_the_username() {
+        let app = TestApp::new().await;
+        let user_id = app.sign_up("alice-liddell").await;
+        let used = password_reset::issue_reset_token(&app.pool, &user_id)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reset_password(&app, &used, "a brand new passphrase")
+                .await
+                .status,
+            StatusCode::NO_CONTENT
+        );
+
+        let expired = password_reset::issue_reset_token(&app.pool, &user_id)
+            .await
+            .unwrap();
+
+        sqlx::query("UPDATE password_reset_tokens SET expires_at = ? WHERE user_id = ?;")
+            .bind(Utc::now() - chrono::Duration::minutes(1))
+            .bind(&user_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        // A live token would fail the password policy for matching the username.
+        for token in [used, expired] {
+            let reset = reset_password(&app, &token, "alice-liddell").await;
+
+            assert_eq!(reset.status, StatusCode::BAD_REQUEST);
+            assert!(!reset.body.to_string().contains("username"));
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_resets_leave_the_token_and_sessions_alone() {
+        let app = TestApp::new().await;
+        let (user_id, access_token) = app.user("alice").await;
+        let token = password_reset::issue_reset_token(&app.pool, &user_id)
+            .await
+            .unwrap();
+
+        sqlx::query(
+            "
+            CREATE TRIGGER fail_password_change BEFORE UPDATE OF password ON users
+            BEGIN
+                SELECT RAISE(ABORT, 'password cannot be changed');
+            END;
+            ",
+        )
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            reset_password(&app, &token, "a brand new passphrase")
+                .await
+                .status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let used_at: Option<String> =
+            sqlx::query_scalar("SELECT used_at FROM password_reset_tokens WHERE user_id = ?;")
+                .bind(&user_id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+
+        assert!(used_at.is_none());
+
+        let read = app
+            .request(Method::GET, "/v1/users/me", Some(&access_token), None)
+            .await;
+
+        assert_eq!(read.status, StatusCode::OK);
+
+        sqlx::query("DROP TRIGGER fail_password_change;")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reset_password(&app, &token, "a brand new passphrase")
+                .await
+                .status,
+            StatusCode::NO_CONTENT
+        );
+
+        let read = app
+            .request(Method::GET, "/v1/users/me", Some(&access_token), None)
+            .await;
+
+        assert_eq!(read.status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn create_personal_access_token(app: &TestApp, access_token: &str) -> String {
+        let response = app
+            .request(
+                Method::POST,
+                "/v1/users/tokens",
+                Some(access_token),
+                Some(json!({ "name": "script", "scopes": ["games:read"] })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        response.body["token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn signing_out_everywhere_revokes_personal_access_tokens() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let personal_access_token = create_personal_access_token(&app, &access_token).await;
+
+        let before = app
+            .request(Method::GET, "/v1/games", Some(&personal_access_token), None)
+            .await;
+
+        assert_eq!(before.status, StatusCode::OK);
+
+        let sign_out = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-out/everywhere",
+                Some(&access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(sign_out.status, StatusCode::NO_CONTENT);
+
+        let after = app
+            .request(Method::GET, "/v1/games", Some(&personal_access_token), None)
+            .await;
+
+        assert_eq!(after.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn scheduling_the_deletion_revokes_personal_access_tokens() {
+        let app = TestApp::new().await;
+        let (_, access_token) = app.user("alice").await;
+        let personal_access_token = create_personal_access_token(&app, &access_token).await;
+
+        let deletion = app
+            .request(Method::DELETE, "/v1/users/me", Some(&access_token), None)
+            .await;
+
+        assert_eq!(deletion.status, StatusCode::ACCEPTED);
+
+        let after = app
+            .request(Method::GET, "/v1/games", Some(&personal_access_token), None)
+            .await;
+
+        assert_eq!(after.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn admins_can_access_the_accounts_of_others() {
+        let app = TestApp::new().await;
+        let (alice, _) = app.user("alice").await;
+        let (_, admin_token) = app.user_with_role("admin", "admin").await;
+
+        let read = app
+            .request(
+                Method::GET,
+                &format!("/v1/users/{}", alice),
+                Some(&admin_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(read.status, StatusCode::OK);
+        assert_eq!(read.body["id"], alice.as_str());
+    }
+
+    #[tokio::test]
+    async fn audit_events_of_the_own_account_are_listed() {
+        let app = TestApp::new().await;
+        let (alice, alice_token) = app.user("alice").await;
+        app.user("bob").await;
+
+        let response = app
+            .request(
+                Method::GET,
+                "/v1/users/me/audit-events",
+                Some(&alice_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body["total"], 2);
+
+        let items = response.body["items"].as_array().unwrap();
+
+        // Newest first.
+        assert_eq!(items[0]["action"], "sign_in");
+        assert_eq!(items[1]["action"], "sign_up");
+        assert!(items.iter().all(|item| item["actor_id"] == alice.as_str()));
+
+        let page = app
+            .request(
+                Method::GET,
+                "/v1/users/me/audit-events?page=2&per_page=1",
+                Some(&alice_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(page.body["total"], 2);
+        assert_eq!(page.body["items"].as_array().unwrap().len(), 1);
+        assert_eq!(page.body["items"][0]["action"], "sign_up");
+    }
+
+    #[tokio::test]
+    async fn failed_audit_writes_do_not_fail_the_action() {
+        let app = TestApp::new().await;
+
+        sqlx::query("DROP TABLE audit_events;")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        let (_, access_token) = app.user("alice").await;
+
+        let response = app
+            .request(
+                Method::PATCH,
+                "/v1/users/me",
+                Some(&access_token),
+                Some(json!({ "username": "alicia" })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        let profile = app
+            .request(Method::GET, "/v1/users/me", Some(&access_token), None)
+            .await;
+
+        assert_eq!(profile.body["username"], "alicia");
+    }
+
+    #[tokio::test]
+    async fn signing_in_during_the_grace_period_keeps_the_account() {
+        let app = TestApp::new().await;
+        let (user_id, access_token) = app.user("alice").await;
+
+        let deletion = app
+            .request(Method::DELETE, "/v1/users/me", Some(&access_token), None)
+            .await;
+
+        assert_eq!(deletion.status, StatusCode::ACCEPTED);
+        assert!(deletion.body["scheduled_for"].is_string());
+
+        let response = app.sign_in("alice").await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        let access_token = response.body["access_token"].as_str().unwrap();
+        let events = app
+            .request(
+                Method::GET,
+                "/v1/users/me/audit-events",
+                Some(access_token),
+                None,
+            )
+            .await;
+
+        assert_eq!(events.body["items"][0]["action"], "sign_in");
+        assert_eq!(
+            events.body["items"][1]["action"],
+            "account_deletion_cancelled"
+        );
+
+        account_deletion::purge_due_accounts(&app.pool)
+            .await
+            .unwrap();
+
+        let profile = app
+            .request(Method::GET, "/v1/users/me", Some(access_token), None)
+            .await;
+
+        assert_eq!(profile.status, StatusCode::OK);
+        assert_eq!(profile.body["id"], user_id.as_str());
+    }
+
+    /// Values of the cookies that the response sets, along with their attributes.
+    fn set_cookies(response: &TestResponse) -> HashMap<String, (String, String)> {
+        response
+            .headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|header_value| {
+                let header_value = header_value.to_str().unwrap();
+                let (cookie, attributes) = header_value.split_once(';').unwrap();
+                let (name, value) = cookie.split_once('=').unwrap();
+
+                (
+                    name.to_string(),
+                    (value.to_string(), attributes.trim().to_string()),
+                )
+            })
+            .collect()
+    }
+
+    /// Sends a request the way a browser would, with cookies instead of an Authorization header.
+    async fn send_with_cookies(
+        app: &TestApp,
+        method: Method,
+        uri: &str,
+        cookies: &[(&str, &str)],
+        csrf_token: Option<&str>,
+    ) -> TestResponse {
+        let cookie = cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(COOKIE, cookie)
+            .header(CONTENT_TYPE, "application/json");
+
+        if let Some(csrf_token) = csrf_token {
+            request = request.header(CSRF_TOKEN_HEADER, csrf_token);
+        }
+
+        app.send(request.body(Body::from("{}")).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn cookie_sessions_round_trip_with_csrf_protection() {
+        let app = TestApp::with_configuration(Configuration {
+            cookies: CookieConfiguration {
+                transport: TokenTransport::Cookie,
+                ..testing::configuration().cookies
+            },
+            ..testing::configuration()
+        })
+        .await;
+        app.sign_up("alice").await;
+
+        let sign_in = app.sign_in("alice").await;
+
+        assert_eq!(sign_in.status, StatusCode::OK);
+        assert!(sign_in.body.get("access_token").is_none());
+
+        let cookies = set_cookies(&sign_in);
+        let (access_token, _) = &cookies[ACCESS_TOKEN_COOKIE];
+        let (refresh_token, refresh_attributes) = &cookies[REFRESH_TOKEN_COOKIE];
+        let (csrf_token, _) = &cookies[CSRF_TOKEN_COOKIE];
+
+        assert_eq!(sign_in.body["csrf_token"], csrf_token.as_str());
+        assert!(refresh_attributes.contains(&format!("Path={}", REFRESH_TOKEN_COOKIE_PATH)));
+        assert!(refresh_attributes.contains("HttpOnly"));
+
+        let profile = send_with_cookies(
+            &app,
+            Method::GET,
+            "/v1/users/me",
+            &[(ACCESS_TOKEN_COOKIE, access_token.as_str())],
+            None,
+        )
+        .await;
+
+        assert_eq!(profile.status, StatusCode::OK);
+
+        let refresh_cookies = [
+            (REFRESH_TOKEN_COOKIE, refresh_token.as_str()),
+            (CSRF_TOKEN_COOKIE, csrf_token.as_str()),
+        ];
+
+        // Without the header that only the web client can fill in, the cookies are not enough.
+        let forged = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/token/refresh",
+            &refresh_cookies,
+            None,
+        )
+        .await;
+
+        assert_eq!(forged.status, StatusCode::BAD_REQUEST);
+
+        let forged = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/token/refresh",
+            &refresh_cookies,
+            Some("guessed"),
+        )
+        .await;
+
+        assert_eq!(forged.status, StatusCode::BAD_REQUEST);
+
+        let refresh = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/token/refresh",
+            &refresh_cookies,
+            Some(csrf_token.as_str()),
+        )
+        .await;
+
+        assert_eq!(refresh.status, StatusCode::OK);
+
+        let cookies = set_cookies(&refresh);
+        let (access_token, _) = &cookies[ACCESS_TOKEN_COOKIE];
+        let (rotated_refresh_token, _) = &cookies[REFRESH_TOKEN_COOKIE];
+        let (csrf_token, _) = &cookies[CSRF_TOKEN_COOKIE];
+
+        assert_ne!(rotated_refresh_token, refresh_token);
+
+        let session_cookies = [
+            (ACCESS_TOKEN_COOKIE, access_token.as_str()),
+            (CSRF_TOKEN_COOKIE, csrf_token.as_str()),
+        ];
+
+        let forged = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/sign-out",
+            &session_cookies,
+            None,
+        )
+        .await;
+
+        assert_eq!(forged.status, StatusCode::UNAUTHORIZED);
+
+        let sign_out = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/sign-out",
+            &session_cookies,
+            Some(csrf_token.as_str()),
+        )
+        .await;
+
+        assert_eq!(sign_out.status, StatusCode::NO_CONTENT);
+
+        let cleared = set_cookies(&sign_out);
+
+        for name in [ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE, CSRF_TOKEN_COOKIE] {
+            assert_eq!(cleared[name].0, "");
+            assert!(cleared[name].1.contains("Max-Age=0"));
+        }
+
+        let refresh = send_with_cookies(
+            &app,
+            Method::POST,
+            "/v1/users/token/refresh",
+            &[
+                (REFRESH_TOKEN_COOKIE, rotated_refresh_token.as_str()),
+                (CSRF_TOKEN_COOKIE, csrf_token.as_str()),
+            ],
+            Some(csrf_token.as_str()),
+        )
+        .await;
+
+        assert_eq!(refresh.status, StatusCode::UNAUTHORIZED);
+    }
+
+    async fn provider_app() -> (TestApp, MockIdentityProvider) {
+        let identity_provider = MockIdentityProvider::start();
+        let app = TestApp::with_configuration(Configuration {
+            oidc: OidcConfiguration {
+                providers: vec![identity_provider.provider()],
+            },
+            ..testing::configuration()
+        })
+        .await;
+
+        (app, identity_provider)
+    }
+
+    async fn sign_in_with_provider(
+        app: &TestApp,
+        identity_provider: &MockIdentityProvider,
+        email: &str,
+    ) -> TestResponse {
+        let authorization = app
+            .request(Method::GET, "/v1/users/oidc/mock/authorize", None, None)
+            .await;
+
+        assert_eq!(authorization.status, StatusCode::OK);
+
+        let parameters =
+            testing::query_parameters(authorization.body["authorization_url"].as_str().unwrap());
+        let code = identity_provider.issue_code(MockAuthorization {
+            subject: format!("subject-of-{}", email),
+            email: Some(email.to_string()),
+            nonce: parameters["nonce"].clone(),
+            code_challenge: parameters["code_challenge"].clone(),
+        });
+
+        app.request(
+            Method::POST,
+            "/v1/users/oidc/mock/callback",
+            None,
+            Some(json!({ "code": code, "state": parameters["state"] })),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn providers_sign_new_users_in() {
+        let (app, identity_provider) = provider_app().await;
+
+        let response = sign_in_with_provider(&app, &identity_provider, "alice@example.com").await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body["access_token"].is_string());
+    }
+
+    #[tokio::test]
+    async fn providers_do_not_skip_the_second_factor() {
+        let (app, identity_provider) = provider_app().await;
+        let user_id = app.sign_up("alice").await;
+        let secret = two_factor::encode_base32(b"12345678901234567890");
+
+        sqlx::query(
+            "
+            UPDATE users
+            SET email = 'alice@example.com',
+                email_verified_at = CURRENT_TIMESTAMP,
+                totp_secret = ?1,
+                totp_enabled_at = CURRENT_TIMESTAMP
+            WHERE id = ?2;
+            ",
+        )
+        .bind(&secret)
+        .bind(&user_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+
+        let response = sign_in_with_provider(&app, &identity_provider, "alice@example.com").await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.get("access_token").is_none());
+
+        let challenge_token = response.body["challenge_token"].as_str().unwrap();
+        let step = Utc::now().timestamp() as u64 / two_factor::PERIOD_SECONDS;
+        let code = format!(
+            "{:06}",
+            two_factor::hotp(&two_factor::decode_base32(&secret).unwrap(), step)
+        );
+
+        let response = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-in/two-factor",
+                None,
+                Some(json!({ "challenge_token": challenge_token, "code": code })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body["access_token"].is_string());
+    }
+
+    #[tokio::test]
+    async fn password_policy_violations_are_reported_per_field() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-up",
+                None,
+                Some(json!({ "username": "alice", "password": "alice" })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.body,
+            json!({
+                "message": "validation failed",
+                "errors": {
+                    "password": [
+                        "must be at least 10 characters long",
+                        "must not be the same as the username",
+                    ]
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn signing_in_upgrades_outdated_password_hashes() {
+        let app = TestApp::new().await;
+        let user_id = app.sign_up("alice").await;
+        let baseline = argon2::hash_encoded(
+            PASSWORD.as_bytes(),
+            b"somesaltsomesalt",
+            &argon2::Config::default(),
+        )
+        .unwrap();
+
+        sqlx::query("UPDATE users SET password = ?1 WHERE id = ?2;")
+            .bind(&baseline)
+            .bind(&user_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::OK);
+
+        let upgraded: String = sqlx::query_scalar("SELECT password FROM users WHERE id = ?;")
+            .bind(&user_id)
+            .fetch_one(&app.pool)
+            .await
+            .unwrap();
+
+        assert_ne!(upgraded, baseline);
+        assert!(upgraded.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(!password::needs_rehash(
+            &upgraded,
+            &app.configuration.password
+        ));
+
+        // The upgraded hash still matches the password.
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn partial_updates_leave_other_fields_alone() {
+        let app = TestApp::new().await;
+        let (alice, token) = app.user("alice").await;
+        let uri = format!("/v1/users/{}", alice);
+
+        let update = app
+            .request(
+                Method::PATCH,
+                "/v1/users/me",
+                Some(&token),
+                Some(json!({ "email": "alice@example.com" })),
+            )
+            .await;
+
+        assert!(update.status.is_success());
+
+        let read = app.request(Method::GET, &uri, Some(&token), None).await;
+
+        assert_eq!(read.body["username"], "alice");
+        assert_eq!(read.body["email"], "alice@example.com");
+
+        let update = app
+            .request(
+                Method::PATCH,
+                &uri,
+                Some(&token),
+                Some(json!({ "username": "alicia" })),
+            )
+            .await;
+
+        assert!(update.status.is_success());
+
+        let read = app.request(Method::GET, &uri, Some(&token), None).await;
+
+        assert_eq!(read.body["username"], "alicia");
+        assert_eq!(read.body["email"], "alice@example.com");
+    }
+
+    #[tokio::test]
+    async fn taken_usernames_conflict() {
+        let app = TestApp::new().await;
+        let (alice, token) = app.user("alice").await;
+
+        app.sign_up("bob").await;
+
+        for uri in ["/v1/users/me".to_string(), format!("/v1/users/{}", alice)] {
+            let update = app
+                .request(
+                    Method::PATCH,
+                    &uri,
+                    Some(&token),
+                    Some(json!({ "username": "bob" })),
+                )
+                .await;
+
+            assert_eq!(update.status, StatusCode::CONFLICT);
+        }
+
+        // Keeping one's own username is no conflict.
+        let update = app
+            .request(
+                Method::PATCH,
+                "/v1/users/me",
+                Some(&token),
+                Some(json!({ "username": "alice" })),
+            )
+            .await;
+
+        assert!(update.status.is_success());
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_requires_the_current_one() {
+        let app = TestApp::new().await;
+        let (_, token) = app.user("alice").await;
+        let new_password = "a brand new passphrase";
+
+        let change = app
+            .request(
+                Method::POST,
+                "/v1/users/password/change",
+                Some(&token),
+                Some(json!({ "current_password": "wrong password", "new_password": new_password })),
+            )
+            .await;
+
+        assert_eq!(change.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_revokes_earlier_tokens() {
+        let app = TestApp::new().await;
+        let (_, token) = app.user("alice").await;
+        let other_session = app.sign_in("alice").await;
+        let new_password = "a brand new passphrase";
+
+        let change = app
+            .request(
+                Method::POST,
+                "/v1/users/password/change",
+                Some(&token),
+                Some(json!({ "current_password": PASSWORD, "new_password": new_password })),
+            )
+            .await;
+
+        assert_eq!(change.status, StatusCode::OK);
+
+        for earlier_token in [
+            token.as_str(),
+            other_session.body["access_token"].as_str().unwrap(),
+        ] {
+            let read = app
+                .request(Method::GET, "/v1/users/me", Some(earlier_token), None)
+                .await;
+
+            assert_eq!(read.status, StatusCode::UNAUTHORIZED);
+        }
+
+        let refresh = app
+            .request(
+                Method::POST,
+                "/v1/users/token/refresh",
+                None,
+                Some(json!({ "refresh_token": other_session.body["refresh_token"] })),
+            )
+            .await;
+
+        assert_eq!(refresh.status, StatusCode::UNAUTHORIZED);
+
+        // The current session goes on with the returned token.
+        let read = app
+            .request(
+                Method::GET,
+                "/v1/users/me",
+                Some(change.body["access_token"].as_str().unwrap()),
+                None,
+            )
+            .await;
+
+        assert_eq!(read.status, StatusCode::OK);
+        assert_eq!(app.sign_in("alice").await.status, StatusCode::UNAUTHORIZED);
+
+        let sign_in = app
+            .request(
+                Method::POST,
+                "/v1/users/sign-in",
+                None,
+                Some(json!({ "username": "alice", "password": new_password })),
+            )
+            .await;
+
+        assert_eq!(sign_in.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn the_profile_summarizes_only_the_own_games() {
+        let app = TestApp::new().await;
+        let (_, alice_token) = app.user("alice").await;
+        let (_, bob_token) = app.user("bob").await;
+
+        let alice_games = [
+            json!({ "title": "Hollow Knight", "status": "completed", "categories": ["metroidvania", "indie"] }),
+            json!({ "title": "Celeste", "status": "completed", "categories": ["indie"] }),
+            json!({ "title": "Hades", "status": "progressing", "categories": ["roguelike", "indie"] }),
+            json!({ "title": "Outer Wilds", "status": "untried" }),
+            json!({ "title": "Dark Souls", "status": "ended", "categories": ["rpg"] }),
+            json!({ "title": "Tetris" }),
+        ];
+        let bob_games = [
+            json!({ "title": "Doom", "status": "completed", "categories": ["shooter"] }),
+            json!({ "title": "Quake", "categories": ["shooter", "indie"] }),
+        ];
+
+        for (access_token, games) in [
+            (&alice_token, &alice_games[..]),
+            (&bob_token, &bob_games[..]),
+        ] {
+            for game in games {
+                let response = app
+                    .request(
+                        Method::POST,
+                        "/v1/games",
+                        Some(access_token),
+                        Some(game.clone()),
+                    )
+                    .await;
+
+                assert_eq!(response.status, StatusCode::CREATED);
+            }
+        }
+
+        let profile = app
+            .request(Method::GET, "/v1/users/me", Some(&alice_token), None)
+            .await;
+
+        assert_eq!(profile.status, StatusCode::OK);
+        assert_eq!(
+            profile.body["summary"],
+            json!({
+                "games": 6,
+                "categories": 4,
+                "games_by_status": {
+                    "untried": 1,
+                    "progressing": 1,
+                    "ended": 1,
+                    "completed": 2,
+                    "none": 1,
+                },
+            })
+        );
+
+        let profile = app
+            .request(Method::GET, "/v1/users/me", Some(&bob_token), None)
+            .await;
+
+        assert_eq!(
+            profile.body["summary"],
+            json!({
+                "games": 2,
+                "categories": 2,
+                "games_by_status": {
+                    "untried": 0,
+                    "progressing": 0,
+                    "ended": 0,
+                    "completed": 1,
+                    "none": 1,
+                },
+            })
+        );
+    }
 }
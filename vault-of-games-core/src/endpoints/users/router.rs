@@ -1,23 +1,91 @@
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
+use tower_http::auth::AsyncRequireAuthorizationLayer;
 
-use crate::endpoints::Endpoint;
+use crate::{authentication::middleware::JWTAuthorizationLayer, endpoints::Endpoint};
 
 use super::{processor::UsersProcessor, UsersEndpoint};
 
 impl Endpoint for UsersEndpoint {
     fn connect_router() -> Router {
-        let routes = Router::new()
+        let authorized_routes = Router::new()
             .route(
                 "/:id",
                 get(UsersProcessor::read)
                     .patch(UsersProcessor::update)
                     .delete(UsersProcessor::delete),
             )
+            .route(
+                "/me",
+                get(UsersProcessor::read_me)
+                    .patch(UsersProcessor::update_me)
+                    .delete(UsersProcessor::delete_me),
+            )
+            .route("/me/audit-events", get(UsersProcessor::read_audit_events))
+            .route("/me/export", get(UsersProcessor::export))
+            .route("/me/sessions", get(UsersProcessor::read_sessions))
+            .route("/me/sessions/:id", delete(UsersProcessor::delete_session))
+            .route("/password/change", post(UsersProcessor::change_password))
+            .route("/sign-out", post(UsersProcessor::sign_out))
+            .route(
+                "/sign-out/everywhere",
+                post(UsersProcessor::sign_out_everywhere),
+            )
+            .route(
+                "/tokens",
+                get(UsersProcessor::read_personal_access_tokens)
+                    .post(UsersProcessor::create_personal_access_token),
+            )
+            .route(
+                "/tokens/:id",
+                delete(UsersProcessor::delete_personal_access_token),
+            )
+            .route(
+                "/email/verification",
+                post(UsersProcessor::resend_email_verification),
+            )
+            .route(
+                "/two-factor/disable",
+                post(UsersProcessor::disable_two_factor),
+            )
+            .route(
+                "/two-factor/enroll",
+                post(UsersProcessor::enroll_two_factor),
+            )
+            .route(
+                "/two-factor/confirm",
+                post(UsersProcessor::confirm_two_factor),
+            )
+            .route(
+                "/two-factor/recovery-codes",
+                post(UsersProcessor::regenerate_recovery_codes),
+            )
+            .route_layer(AsyncRequireAuthorizationLayer::new(
+                JWTAuthorizationLayer::default(),
+            ));
+
+        let routes = Router::new()
             .route("/sign-in", post(UsersProcessor::sign_in))
-            .route("/sign-up", post(UsersProcessor::sign_up));
+            .route(
+                "/sign-in/two-factor",
+                post(UsersProcessor::sign_in_two_factor),
+            )
+            .route("/sign-up", post(UsersProcessor::sign_up))
+            .route(
+                "/oidc/:provider/authorize",
+                get(UsersProcessor::authorize_with_provider),
+            )
+            .route(
+                "/oidc/:provider/callback",
+                post(UsersProcessor::sign_in_with_provider),
+            )
+            .route("/email/verify", post(UsersProcessor::verify_email))
+            .route("/password/forgot", post(UsersProcessor::forgot_password))
+            .route("/password/reset", post(UsersProcessor::reset_password))
+            .route("/token/refresh", post(UsersProcessor::refresh_token))
+            .merge(authorized_routes);
 
         Router::new().nest("/users", routes)
     }
@@ -0,0 +1,4 @@
+mod processor;
+pub mod router;
+
+pub struct WellKnownEndpoint;
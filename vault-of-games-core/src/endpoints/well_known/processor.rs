@@ -0,0 +1,41 @@
+use anyhow::Result;
+use axum::{response::IntoResponse, Json};
+
+use crate::{authentication::keys::key_ring, error::ProcessorError};
+
+#[derive(Default)]
+pub struct WellKnownProcessor;
+
+impl WellKnownProcessor {
+    pub async fn json_web_key_set() -> Result<impl IntoResponse, ProcessorError> {
+        let key_set = key_ring().to_json_web_key_set()?;
+
+        Ok(Json(key_set))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use crate::testing::TestApp;
+
+    #[tokio::test]
+    async fn key_set_is_published() {
+        let app = TestApp::new().await;
+
+        let response = app
+            .request(Method::GET, "/.well-known/jwks.json", None, None)
+            .await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        let keys = response.body["keys"].as_array().unwrap();
+
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0]["kid"], "test");
+        assert_eq!(keys[0]["alg"], "EdDSA");
+        assert!(keys[0]["x"].is_string());
+        assert!(keys[0].get("d").is_none());
+    }
+}
@@ -0,0 +1,13 @@
+use axum::{routing::get, Router};
+
+use crate::endpoints::Endpoint;
+
+use super::{processor::WellKnownProcessor, WellKnownEndpoint};
+
+impl Endpoint for WellKnownEndpoint {
+    fn connect_router() -> Router {
+        let routes = Router::new().route("/jwks.json", get(WellKnownProcessor::json_web_key_set));
+
+        Router::new().nest("/.well-known", routes)
+    }
+}
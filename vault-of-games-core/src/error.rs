@@ -1,18 +1,29 @@
 use axum::{body::HttpBody, response::IntoResponse};
-use hyper::{header::CONTENT_TYPE, Body, Response, StatusCode};
+use hyper::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    Body, Response, StatusCode,
+};
 use serde_json::json;
 use thiserror::Error;
 
-use crate::authentication::error::AuthenticationError;
+use crate::{authentication::error::AuthenticationError, validation::ValidationErrors};
 
 pub trait MapToStatusCode {
     fn map_to_status_code(&self) -> StatusCode;
 }
 
+/// Extended SQLite result code of a violated `UNIQUE` constraint.
+const SQLITE_CONSTRAINT_UNIQUE: &str = "2067";
+
 impl MapToStatusCode for sqlx::Error {
     fn map_to_status_code(&self) -> StatusCode {
         match self {
             sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            sqlx::Error::Database(error)
+                if error.code().as_deref() == Some(SQLITE_CONSTRAINT_UNIQUE) =>
+            {
+                StatusCode::CONFLICT
+            }
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -24,6 +35,11 @@ pub enum ProcessorError {
     AuthenticationError(#[from] crate::authentication::error::AuthenticationError),
     #[error("database error")]
     DatabaseError(#[from] sqlx::Error),
+    #[error("validation error")]
+    ValidationError(#[from] ValidationErrors),
+    /// The resource changed since the version that the request was based on.
+    #[error("precondition failed")]
+    PreconditionFailed,
 }
 
 impl From<argon2::Error> for ProcessorError {
@@ -32,12 +48,24 @@ impl From<argon2::Error> for ProcessorError {
     }
 }
 
+impl From<ct_codecs::Error> for ProcessorError {
+    fn from(_: ct_codecs::Error) -> Self {
+        Self::AuthenticationError(AuthenticationError::TokenCreation)
+    }
+}
+
 impl From<jwt_simple::Error> for ProcessorError {
     fn from(_: jwt_simple::Error) -> Self {
         Self::AuthenticationError(AuthenticationError::TokenCreation)
     }
 }
 
+impl From<reqwest::Error> for ProcessorError {
+    fn from(_: reqwest::Error) -> Self {
+        Self::AuthenticationError(AuthenticationError::IdentityProvider)
+    }
+}
+
 impl IntoResponse for ProcessorError {
     type Body = Body;
 
@@ -46,26 +74,51 @@ impl IntoResponse for ProcessorError {
     fn into_response(self) -> Response<Self::Body> {
         tracing::error!("{}", self);
 
-        let (status, message) = {
+        let retry_after = match &self {
+            ProcessorError::AuthenticationError(AuthenticationError::TooManyAttempts {
+                retry_after,
+            }) => Some(*retry_after),
+            _ => None,
+        };
+
+        let (status, body) = {
             match self {
                 ProcessorError::AuthenticationError(error) => {
                     tracing::error!("{}", error);
 
-                    (error.map_to_status_code(), format!("{}", error))
+                    (
+                        error.map_to_status_code(),
+                        json!({ "message": format!("{}", error) }),
+                    )
                 }
                 ProcessorError::DatabaseError(error) => {
                     tracing::error!("{}", error);
 
-                    (error.map_to_status_code(), format!("{}", error))
+                    (
+                        error.map_to_status_code(),
+                        json!({ "message": format!("{}", error) }),
+                    )
                 }
+                ProcessorError::ValidationError(errors) => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    json!({ "message": format!("{}", errors), "errors": errors }),
+                ),
+                error @ ProcessorError::PreconditionFailed => (
+                    StatusCode::PRECONDITION_FAILED,
+                    json!({ "message": format!("{}", error) }),
+                ),
             }
         };
 
-        let response = Response::builder()
+        let mut response = Response::builder()
             .status(status)
-            .header(CONTENT_TYPE, "application/json")
-            .body(Body::from(json!({ "message": message }).to_string()))
-            .unwrap();
+            .header(CONTENT_TYPE, "application/json");
+
+        if let Some(retry_after) = retry_after {
+            response = response.header(RETRY_AFTER, retry_after);
+        }
+
+        let response = response.body(Body::from(body.to_string())).unwrap();
 
         response
     }
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::{Mail, Mailer};
+
+/// Writes mail to the log instead of sending it, and into `directory` as well when given, so
+/// that development setups can follow links without a mail server.
+pub struct LogMailer {
+    directory: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Self { directory }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        tracing::info!(
+            "Mail to {} with subject \"{}\":\n{}",
+            mail.to,
+            mail.subject,
+            mail.body
+        );
+
+        if let Some(directory) = &self.directory {
+            tokio::fs::create_dir_all(directory).await?;
+
+            let path = directory.join(format!(
+                "{}-{}.txt",
+                Utc::now().format("%Y%m%d%H%M%S"),
+                Uuid::new_v4()
+            ));
+
+            tokio::fs::write(
+                path,
+                format!(
+                    "To: {}\nSubject: {}\n\n{}\n",
+                    mail.to, mail.subject, mail.body
+                ),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
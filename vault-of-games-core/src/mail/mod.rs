@@ -0,0 +1,49 @@
+pub mod log;
+pub mod smtp;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::configuration::{MailConfiguration, MailTransport};
+
+use self::{log::LogMailer, smtp::SmtpMailer};
+
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    pub fn new(to: String, subject: String, body: String) -> Self {
+        Self { to, subject, body }
+    }
+}
+
+/// Delivers mail to users, through SMTP in production and into a log sink during development.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<()>;
+}
+
+pub fn from_configuration(configuration: &MailConfiguration) -> Result<Arc<dyn Mailer>> {
+    let mailer: Arc<dyn Mailer> = match &configuration.transport {
+        MailTransport::Log { directory } => Arc::new(LogMailer::new(directory.clone())),
+        MailTransport::Smtp {
+            host,
+            port,
+            username,
+            password,
+        } => Arc::new(SmtpMailer::new(
+            host,
+            *port,
+            username.clone().zip(password.clone()),
+            configuration.sender.clone(),
+        )?),
+    };
+
+    Ok(mailer)
+}
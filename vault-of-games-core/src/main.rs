@@ -1,12 +1,22 @@
 use anyhow::Result;
 use dotenv::dotenv;
 
+use crate::{authentication::keys, cli::Command};
+
+mod audit;
 mod authentication;
+mod cli;
+mod configuration;
 mod database;
 mod endpoints;
 mod error;
+mod mail;
+mod pagination;
 mod router;
 mod server;
+#[cfg(test)]
+mod testing;
+mod validation;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,7 +24,10 @@ async fn main() -> Result<()> {
 
     tracing_subscriber::fmt::init();
 
-    server::run().await?;
+    match Command::parse(std::env::args().skip(1))? {
+        Command::Serve => server::run().await?,
+        Command::Keys(command) => command.execute(&keys::keys_directory())?,
+    }
 
     Ok(())
 }
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PER_PAGE: u32 = 20;
+pub const MAX_PER_PAGE: u32 = 100;
+
+/// Page requested through the `page` and `per_page` query parameters, counting pages from 1.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    page: Option<u32>,
+    #[serde(default)]
+    per_page: Option<u32>,
+}
+
+impl Pagination {
+    pub fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn limit(&self) -> i64 {
+        i64::from(self.per_page())
+    }
+
+    pub fn offset(&self) -> i64 {
+        i64::from(self.page() - 1) * self.limit()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub per_page: u32,
+    /// Number of items across all pages.
+    pub total: i64,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, pagination: &Pagination, total: i64) -> Self {
+        Self {
+            items,
+            page: pagination.page(),
+            per_page: pagination.per_page(),
+            total,
+        }
+    }
+}
+
+/// Page of a listing that continues after the position encoded in `next_cursor`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    /// Null on the last page.
+    pub next_cursor: Option<String>,
+    /// Number of items across all pages.
+    pub total: i64,
+}
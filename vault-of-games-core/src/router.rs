@@ -1,6 +1,9 @@
 use axum::Router;
 
-use crate::endpoints::{games::GamesEndpoint, users::UsersEndpoint, Endpoint};
+use crate::endpoints::{
+    admin::AdminEndpoint, games::GamesEndpoint, users::UsersEndpoint,
+    well_known::WellKnownEndpoint, Endpoint,
+};
 
 pub trait MountEndpointsExt {
     fn mount_endpoints(self) -> Self;
@@ -10,11 +13,14 @@ impl MountEndpointsExt for Router {
     fn mount_endpoints(self) -> Self {
         let games = GamesEndpoint::connect_router();
         let users = UsersEndpoint::connect_router();
+        let admin = AdminEndpoint::connect_router();
 
-        let endpoints = Router::new().merge(games).merge(users);
+        let endpoints = Router::new().merge(games).merge(users).merge(admin);
 
         let v1 = Router::new().nest("/v1", endpoints);
 
-        self.merge(v1)
+        let well_known = WellKnownEndpoint::connect_router();
+
+        self.merge(v1).merge(well_known)
     }
 }
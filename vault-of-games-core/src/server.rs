@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use axum::{
@@ -7,11 +7,27 @@ use axum::{
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
-use crate::{database::DatabaseConnectionPool, router::MountEndpointsExt};
+use crate::{
+    authentication::{account_deletion, keys, sessions},
+    configuration::Configuration,
+    database::DatabaseConnectionPool,
+    mail,
+    router::MountEndpointsExt,
+};
 
 pub async fn run() -> Result<()> {
+    keys::initialize_key_ring()?;
+
+    let configuration = Arc::new(Configuration::from_environment()?);
+    let mailer = mail::from_configuration(&configuration.mail)?;
+
     let pool = DatabaseConnectionPool::connect(&std::env::var("DATABASE_URL")?).await?;
 
+    tokio::spawn(sessions::flush_last_seen_periodically(pool.clone()));
+    tokio::spawn(account_deletion::purge_due_accounts_periodically(
+        pool.clone(),
+    ));
+
     let middleware = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|error: BoxError| {
             if error.is::<tower::timeout::error::Elapsed>() {
@@ -26,6 +42,8 @@ pub async fn run() -> Result<()> {
         .timeout(Duration::from_secs(10))
         .layer(TraceLayer::new_for_http())
         .layer(AddExtensionLayer::new(pool))
+        .layer(AddExtensionLayer::new(configuration))
+        .layer(AddExtensionLayer::new(mailer))
         .into_inner();
 
     let router = Router::new().mount_endpoints().layer(middleware);
@@ -35,7 +53,7 @@ pub async fn run() -> Result<()> {
     tracing::debug!("Listening on: {}", address);
 
     Server::bind(&address)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr, _>())
         .await?;
 
     Ok(())
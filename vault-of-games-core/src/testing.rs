@@ -0,0 +1,491 @@
+//! Helpers shared by the tests, which run the endpoints against a fresh in-memory database each.
+
+use std::{
+    collections::HashMap,
+    fs,
+    net::{SocketAddr, TcpListener},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Extension, Form},
+    routing::{get, post},
+    AddExtensionLayer, Json, Router, Server,
+};
+use ct_codecs::{Base64UrlSafeNoPadding, Encoder};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderMap, Method, Request, StatusCode,
+};
+use jwt_simple::prelude::{
+    Claims, Duration as JwtDuration, ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair,
+};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{keys, tokens},
+    configuration::{
+        AccountDeletionConfiguration, Configuration, CookieConfiguration,
+        EmailVerificationConfiguration, LockoutConfiguration, MailConfiguration, MailTransport,
+        OidcConfiguration, OidcProviderConfiguration, PasswordConfiguration, TokenTransport,
+    },
+    database::DatabaseConnectionPool,
+    mail::{Mail, Mailer},
+    router::MountEndpointsExt,
+};
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+/// Every connection to `sqlite::memory:` opens a database of its own, so the pool keeps a single
+/// one around for as long as the test runs.
+pub async fn database() -> DatabaseConnectionPool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    sqlx::migrate!().run(&pool).await.unwrap();
+
+    pool
+}
+
+/// A database file in `directory` behind a pool of several connections, for tests of requests
+/// that run at the same time.
+pub async fn shared_database(directory: &Path) -> DatabaseConnectionPool {
+    let options = SqliteConnectOptions::new()
+        .filename(directory.join("vault-of-games.db"))
+        .create_if_missing(true);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(8)
+        .connect_with(options)
+        .await
+        .unwrap();
+
+    sqlx::migrate!().run(&pool).await.unwrap();
+
+    pool
+}
+
+/// The defaults of the environment, with cheap argon2 parameters.
+pub fn configuration() -> Configuration {
+    Configuration {
+        lockout: LockoutConfiguration {
+            threshold: 5,
+            base_duration_seconds: 30,
+            max_duration_seconds: 3600,
+            window_seconds: 900,
+        },
+        password: PasswordConfiguration {
+            min_length: 10,
+            memory_cost: 1024,
+            time_cost: 1,
+            lanes: 1,
+        },
+        mail: MailConfiguration {
+            transport: MailTransport::Log { directory: None },
+            sender: "Vault of Games <no-reply@localhost>".to_string(),
+            public_url: "http://localhost:3000".to_string(),
+        },
+        email_verification: EmailVerificationConfiguration {
+            token_lifetime_minutes: 1440,
+            required_for_sign_in: false,
+            required_for_password_reset: true,
+        },
+        oidc: OidcConfiguration::default(),
+        account_deletion: AccountDeletionConfiguration {
+            grace_period_days: 30,
+        },
+        cookies: CookieConfiguration {
+            transport: TokenTransport::Header,
+            secure: true,
+            domain: None,
+        },
+    }
+}
+
+/// Keeps the mail instead of sending it.
+#[derive(Default)]
+pub struct CapturingMailer {
+    mail: Mutex<Vec<Mail>>,
+}
+
+impl CapturingMailer {
+    pub fn sent(&self) -> Vec<Mail> {
+        self.mail.lock().unwrap().clone()
+    }
+
+    /// Waits for mail that is sent after the response, giving up after a second.
+    pub async fn wait_for(&self, count: usize) -> Vec<Mail> {
+        for _ in 0..100 {
+            let sent = self.sent();
+
+            if sent.len() >= count {
+                return sent;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("expected {} mail, got {}", count, self.sent().len());
+    }
+}
+
+#[async_trait]
+impl Mailer for CapturingMailer {
+    async fn send(&self, mail: Mail) -> Result<()> {
+        self.mail.lock().unwrap().push(mail);
+
+        Ok(())
+    }
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// `Null` for empty bodies.
+    pub body: Value,
+}
+
+/// The endpoints with the same extensions as the server, called without a socket.
+pub struct TestApp {
+    pub pool: DatabaseConnectionPool,
+    pub configuration: Arc<Configuration>,
+    pub mailer: Arc<CapturingMailer>,
+    router: Router,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_configuration(configuration()).await
+    }
+
+    pub async fn with_configuration(configuration: Configuration) -> Self {
+        keys::initialize_test_key_ring();
+
+        let pool = database().await;
+        let configuration = Arc::new(configuration);
+        let mailer = Arc::new(CapturingMailer::default());
+
+        let router = Router::new()
+            .mount_endpoints()
+            .layer(AddExtensionLayer::new(pool.clone()))
+            .layer(AddExtensionLayer::new(configuration.clone()))
+            .layer(AddExtensionLayer::new(mailer.clone() as Arc<dyn Mailer>));
+
+        Self {
+            pool,
+            configuration,
+            mailer,
+            router,
+        }
+    }
+
+    /// Sends the request as if it came from `127.0.0.1`.
+    pub async fn send(&self, mut request: Request<Body>) -> TestResponse {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        };
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+
+        self.send(request.unwrap()).await
+    }
+
+    /// Signs up a user with [`PASSWORD`], returning their ID.
+    pub async fn sign_up(&self, username: &str) -> String {
+        let response = self
+            .request(
+                Method::POST,
+                "/v1/users/sign-up",
+                None,
+                Some(json!({ "username": username, "password": PASSWORD })),
+            )
+            .await;
+
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        response.body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Signs in with [`PASSWORD`], returning the response of the endpoint.
+    pub async fn sign_in(&self, username: &str) -> TestResponse {
+        self.request(
+            Method::POST,
+            "/v1/users/sign-in",
+            None,
+            Some(json!({ "username": username, "password": PASSWORD })),
+        )
+        .await
+    }
+
+    /// Signs up and in, returning the ID of the user and their access token.
+    pub async fn user(&self, username: &str) -> (String, String) {
+        let user_id = self.sign_up(username).await;
+        let response = self.sign_in(username).await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+
+        (user_id, access_token)
+    }
+
+    /// Signs up a user with the role, such as `admin`, and signs them in.
+    pub async fn user_with_role(&self, username: &str, role: &str) -> (String, String) {
+        let user_id = self.sign_up(username).await;
+
+        sqlx::query("UPDATE users SET role = ?1 WHERE id = ?2;")
+            .bind(role)
+            .bind(&user_id)
+            .execute(&self.pool)
+            .await
+            .unwrap();
+
+        let response = self.sign_in(username).await;
+
+        assert_eq!(response.status, StatusCode::OK);
+
+        let access_token = response.body["access_token"].as_str().unwrap().to_string();
+
+        (user_id, access_token)
+    }
+}
+
+/// What the mock identity provider asserts for an authorization code.
+#[derive(Clone)]
+pub struct MockAuthorization {
+    pub subject: String,
+    pub email: Option<String>,
+    pub nonce: String,
+    pub code_challenge: String,
+}
+
+struct MockIdentityProviderState {
+    issuer: String,
+    key_pair: ES256KeyPair,
+    codes: Mutex<HashMap<String, MockAuthorization>>,
+}
+
+/// Identity provider on a local port, which signs ES256 ID tokens for the codes registered with
+/// [`MockIdentityProvider::issue_code`].
+pub struct MockIdentityProvider {
+    state: Arc<MockIdentityProviderState>,
+}
+
+impl MockIdentityProvider {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(MockIdentityProviderState {
+            issuer,
+            key_pair: ES256KeyPair::generate().with_key_id("mock"),
+            codes: Mutex::new(HashMap::new()),
+        });
+
+        let router = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(Self::discovery_document),
+            )
+            .route("/jwks", get(Self::json_web_key_set))
+            .route("/token", post(Self::token))
+            .layer(AddExtensionLayer::new(state.clone()));
+
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+
+        tokio::spawn(server);
+
+        Self { state }
+    }
+
+    pub fn provider(&self) -> OidcProviderConfiguration {
+        OidcProviderConfiguration {
+            name: "mock".to_string(),
+            issuer: self.state.issuer.clone(),
+            client_id: "vault-of-games".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:3000/oidc/callback".to_string(),
+            scopes: "openid email".to_string(),
+        }
+    }
+
+    /// Registers what the token endpoint asserts when the returned code is exchanged.
+    pub fn issue_code(&self, authorization: MockAuthorization) -> String {
+        let code = tokens::generate_opaque_token().unwrap();
+
+        self.state
+            .codes
+            .lock()
+            .unwrap()
+            .insert(code.clone(), authorization);
+
+        code
+    }
+
+    async fn discovery_document(
+        Extension(state): Extension<Arc<MockIdentityProviderState>>,
+    ) -> Json<Value> {
+        Json(json!({
+            "issuer": state.issuer,
+            "authorization_endpoint": format!("{}/authorize", state.issuer),
+            "token_endpoint": format!("{}/token", state.issuer),
+            "jwks_uri": format!("{}/jwks", state.issuer),
+        }))
+    }
+
+    async fn json_web_key_set(
+        Extension(state): Extension<Arc<MockIdentityProviderState>>,
+    ) -> Json<Value> {
+        // Uncompressed SEC1 encoding of the point, behind its 0x04 tag.
+        let point = state
+            .key_pair
+            .public_key()
+            .public_key()
+            .to_bytes_uncompressed();
+
+        Json(json!({
+            "keys": [{
+                "kty": "EC",
+                "kid": "mock",
+                "crv": "P-256",
+                "x": Base64UrlSafeNoPadding::encode_to_string(&point[1..33]).unwrap(),
+                "y": Base64UrlSafeNoPadding::encode_to_string(&point[33..65]).unwrap(),
+            }],
+        }))
+    }
+
+    /// Exchanges a code for an ID token, provided that the verifier matches the challenge.
+    async fn token(
+        Form(form): Form<HashMap<String, String>>,
+        Extension(state): Extension<Arc<MockIdentityProviderState>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let authorization = form
+            .get("code")
+            .and_then(|code| state.codes.lock().unwrap().remove(code))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        let code_challenge = form
+            .get("code_verifier")
+            .map(|verifier| {
+                Base64UrlSafeNoPadding::encode_to_string(hmac_sha256::Hash::hash(
+                    verifier.as_bytes(),
+                ))
+                .unwrap()
+            })
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        if code_challenge != authorization.code_challenge {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let claims = Claims::with_custom_claims(
+            json!({ "email": authorization.email, "email_verified": true }),
+            JwtDuration::from_mins(5),
+        )
+        .with_issuer(&state.issuer)
+        .with_audience(form.get("client_id").ok_or(StatusCode::BAD_REQUEST)?)
+        .with_subject(&authorization.subject)
+        .with_nonce(&authorization.nonce);
+
+        let id_token = state.key_pair.sign(claims).unwrap();
+
+        Ok(Json(
+            json!({ "id_token": id_token, "token_type": "Bearer" }),
+        ))
+    }
+}
+
+/// A directory under the system's temporary directory, removed with everything in it on drop.
+pub struct TemporaryDirectory(PathBuf);
+
+impl TemporaryDirectory {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("vault-of-games-{}", Uuid::new_v4()));
+
+        fs::create_dir(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+impl Deref for TemporaryDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Decodes the query parameters of the URL that the user is sent to.
+pub fn query_parameters(url: &str) -> HashMap<String, String> {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                percent_decode_str(value).decode_utf8().unwrap().to_string(),
+            )
+        })
+        .collect()
+}